@@ -0,0 +1,70 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use vigil_core::{databases::in_mem::InMemDb, eval::EvalResult, types::Event};
+
+/// Copies of the departments dataset appended to the database.
+const SCALE: usize = 2_000;
+
+const QUERIES: [(&str, &str); 2] = [
+    (
+        "grouping",
+        include_str!("../src/resources/department-grouping-ordered.eql"),
+    ),
+    (
+        "whole_event",
+        include_str!("../src/resources/query_whole_event.eql"),
+    ),
+];
+
+fn departments_db() -> InMemDb {
+    let proposes: Vec<serde_json::Value> =
+        serde_json::from_str(include_str!("../src/resources/input/departments.json")).unwrap();
+
+    let mut db = InMemDb::default();
+
+    for _ in 0..SCALE {
+        for propose in &proposes {
+            db.append(
+                propose["subject"].as_str().unwrap(),
+                vec![
+                    Event::builder()
+                        .event_type(propose["type"].as_str().unwrap())
+                        .data(
+                            "application/json",
+                            serde_json::to_vec(&propose["payload"]).unwrap(),
+                        )
+                        .build(),
+                ],
+            )
+            .unwrap();
+        }
+    }
+
+    db
+}
+
+fn projection_cache(c: &mut Criterion) {
+    let mut db = departments_db();
+    let mut group = c.benchmark_group("projection_cache");
+
+    for capacity in [0, SCALE * 6] {
+        db.set_projection_cache(capacity);
+
+        for (name, query) in QUERIES {
+            let label = if capacity == 0 { "uncached" } else { "cached" };
+
+            group.bench_function(format!("{name}/{label}"), |b| {
+                b.iter(|| {
+                    db.run_query(query)
+                        .unwrap()
+                        .collect::<EvalResult<Vec<_>>>()
+                        .unwrap()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, projection_cache);
+criterion_main!(benches);
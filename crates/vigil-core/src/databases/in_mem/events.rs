@@ -1,13 +1,40 @@
+use std::collections::HashSet;
+
 use crate::types::Event;
 
 pub struct IndexedEvents<'a, I> {
     indexes: I,
     events: &'a [Event],
+    deleted: &'a HashSet<usize>,
 }
 
 impl<'a, I> IndexedEvents<'a, I> {
-    pub fn new(indexes: I, events: &'a [Event]) -> Self {
-        Self { indexes, events }
+    pub fn new(indexes: I, events: &'a [Event], deleted: &'a HashSet<usize>) -> Self {
+        Self {
+            indexes,
+            events,
+            deleted,
+        }
+    }
+}
+
+impl<'a, I> IndexedEvents<'a, I>
+where
+    I: Iterator<Item = usize> + 'a,
+{
+    /// Yields every event along with its offset in the log.
+    pub fn with_offsets(mut self) -> impl Iterator<Item = (usize, &'a Event)> + 'a {
+        std::iter::from_fn(move || self.next_entry())
+    }
+
+    fn next_entry(&mut self) -> Option<(usize, &'a Event)> {
+        loop {
+            let idx = self.indexes.next()?;
+
+            if !self.deleted.contains(&idx) {
+                return self.events.get(idx).map(|event| (idx, event));
+            }
+        }
     }
 }
 
@@ -18,7 +45,6 @@ where
     type Item = &'a Event;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let idx = self.indexes.next()?;
-        self.events.get(idx)
+        self.next_entry().map(|(_, event)| event)
     }
 }
@@ -1,41 +1,94 @@
 mod events;
+mod projections;
 mod subject;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    sync::{Arc, Mutex, PoisonError},
+};
 
-use eventql_parser::{Session, Type};
+use chrono::{DateTime, Utc};
+use eventql_parser::{FunArgs, Query, Session, Type, prelude::Typed};
+use serde::Deserialize;
+use uuid::Uuid;
 
 use crate::{
     databases::{
         Error,
         in_mem::{
             events::IndexedEvents,
+            projections::{ProjectionCache, TypeKey},
             subject::{Subject, Subjects},
         },
     },
-    planner::{DataProvider, query_plan},
-    queries::QueryProcessor,
-    types::Event,
-    values::QueryValue,
+    eval::EvalResult,
+    planner::{DataProvider, DataSource, explain, query_plan},
+    queries::{QueryOptions, QueryPlan, QueryProcessor},
+    types::{Decoders, Event},
+    values::{QueryValue, Record},
 };
 
+#[derive(Default)]
 pub struct InMemDb {
     types: HashMap<String, Vec<usize>>,
     subjects: Subject,
+    /// Offset of every event by id. Ids are expected to be unique, the first event appended with
+    /// a given id is the one indexed. Nil ids aren't indexed.
+    ids: HashMap<Uuid, usize>,
     events: Vec<Event>,
-    session: Session,
+    deleted: HashSet<usize>,
+    options: QueryOptions,
+    default_content_type: Option<String>,
+    projections: Option<Mutex<ProjectionCache>>,
+    decoders: Decoders,
+    validate_events: bool,
 }
 
 impl InMemDb {
     pub fn append(&mut self, subject: &str, events: Vec<Event>) -> super::Result<()> {
+        self.append_returning(subject, events).map(|_| ())
+    }
+
+    /// Same as [`InMemDb::append`] but returns the offsets the events were stored at, in the
+    /// order they were given. Those can be looked up with [`InMemDb::get`].
+    pub fn append_returning(
+        &mut self,
+        subject: &str,
+        mut events: Vec<Event>,
+    ) -> super::Result<Vec<usize>> {
         if subject.starts_with('/') {
             return Err(Error::IllegalSubject);
         }
 
+        if self.validate_events {
+            // like the builder does, so events built from `Default` pass validation
+            for event in &mut events {
+                if event.spec_version.is_empty() {
+                    event.spec_version = "1.0".to_string();
+                }
+            }
+
+            // check the whole batch first so a bad event doesn't leave it half appended
+            for event in &events {
+                event.validate().map_err(Error::InvalidEvent)?;
+            }
+        }
+
         let subject_entries = self.subjects.entries(subject.split('/'));
-        let mut next_id = self.events.len();
+        let mut offsets = Vec::with_capacity(events.len());
+
+        for (next_id, mut event) in (self.events.len()..).zip(events) {
+            if event.datacontenttype.is_empty()
+                && let Some(content_type) = &self.default_content_type
+            {
+                event.datacontenttype = content_type.clone();
+            }
+
+            if event.ingested_at == DateTime::<Utc>::default() {
+                event.ingested_at = Utc::now();
+            }
 
-        for event in events {
             // index by types
             self.types
                 .entry(event.event_type.clone())
@@ -45,12 +98,99 @@ impl InMemDb {
             // index by subject
             subject_entries.push(next_id);
 
+            // index by id
+            if !event.id.is_nil() {
+                self.ids.entry(event.id).or_insert(next_id);
+            }
+
             // store the event in the persistent storage
             self.events.push(event);
-            next_id += 1;
+            offsets.push(next_id);
+        }
+
+        Ok(offsets)
+    }
+
+    /// Appends one JSON event per line, each with a `subject`, a `type` and a `payload`. Blank
+    /// lines are skipped. Returns how many events got appended, or the first line that couldn't
+    /// be read. Events from the lines before it stay appended.
+    pub fn ingest_ndjson<R: BufRead>(&mut self, reader: R) -> super::Result<usize> {
+        #[derive(Deserialize)]
+        struct Line {
+            subject: String,
+            #[serde(rename = "type")]
+            event_type: String,
+            payload: serde_json::Value,
+        }
+
+        let mut count = 0;
+
+        for (idx, line) in reader.lines().enumerate() {
+            let malformed = |reason: String| Error::MalformedNdjson {
+                line: idx + 1,
+                reason,
+            };
+
+            let line = line.map_err(|e| malformed(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line = serde_json::from_str::<Line>(&line).map_err(|e| malformed(e.to_string()))?;
+            let event = Event::builder()
+                .subject(line.subject.as_str())
+                .event_type(line.event_type)
+                .json_data(&line.payload)
+                .map_err(|e| malformed(e.to_string()))?
+                .build();
+
+            self.append(&line.subject, vec![event])?;
+
+            count += 1;
         }
 
-        Ok(())
+        Ok(count)
+    }
+
+    pub fn get(&self, offset: usize) -> Option<&Event> {
+        if self.deleted.contains(&offset) {
+            return None;
+        }
+
+        self.events.get(offset)
+    }
+
+    /// Looks up a live event by id, without knowing its subject.
+    pub fn get_by_id(&self, id: Uuid) -> Option<&Event> {
+        self.get(*self.ids.get(&id)?)
+    }
+
+    /// Marks the event `id` as deleted, wherever it's stored. Returns `false` if there was no such
+    /// event left to delete.
+    pub fn delete_by_id(&mut self, id: Uuid) -> bool {
+        self.ids
+            .get(&id)
+            .is_some_and(|offset| self.deleted.insert(*offset))
+    }
+
+    /// Marks the event `id` stored directly under `subject` as deleted, hiding it from iterators
+    /// and queries. Returns `false` if there was no such event left to delete.
+    pub fn delete(&mut self, subject: &str, id: Uuid) -> super::Result<bool> {
+        let subject = self
+            .subjects
+            .lookup(subject)
+            .map_err(|(ancestor, missing)| Error::MissingSubjectSegment {
+                ancestor: ancestor.name().to_owned(),
+                missing: missing.to_owned(),
+            })?;
+
+        let offset = subject
+            .events()
+            .iter()
+            .copied()
+            .find(|offset| !self.deleted.contains(offset) && self.events[*offset].id == id);
+
+        Ok(offset.is_some_and(|offset| self.deleted.insert(offset)))
     }
 
     pub fn iter_types<'a>(&'a self, tpe: &'a str) -> impl Iterator<Item = &'a Event> + 'a {
@@ -62,7 +202,7 @@ impl InMemDb {
             .iter()
             .copied();
 
-        IndexedEvents::new(type_events, self.events.as_slice())
+        IndexedEvents::new(type_events, self.events.as_slice(), &self.deleted)
     }
 
     pub fn iter_subject_events<'a>(
@@ -72,7 +212,80 @@ impl InMemDb {
         let subject_events =
             Subjects::new(path, &self.subjects).flat_map(|sub| sub.events().iter().copied());
 
-        IndexedEvents::new(subject_events, self.events.as_slice())
+        IndexedEvents::new(subject_events, self.events.as_slice(), &self.deleted)
+    }
+
+    /// Like [`InMemDb::iter_subject_events`] but only goes `max_depth` levels below `path`: `0`
+    /// only yields the events of `path` itself, `1` the ones of its immediate children too.
+    pub fn iter_subject_events_depth<'a>(
+        &'a self,
+        path: &'a str,
+        max_depth: usize,
+    ) -> impl Iterator<Item = &'a Event> + 'a {
+        let subject_events = Subjects::new_with_depth(path, &self.subjects, max_depth)
+            .flat_map(|sub| sub.events().iter().copied());
+
+        IndexedEvents::new(subject_events, self.events.as_slice(), &self.deleted)
+    }
+
+    /// Like [`InMemDb::iter_types`] but stops after `limit` events.
+    pub fn iter_types_limited<'a>(
+        &'a self,
+        tpe: &'a str,
+        limit: usize,
+    ) -> impl Iterator<Item = &'a Event> + 'a {
+        self.iter_types(tpe).take(limit)
+    }
+
+    /// Like [`InMemDb::iter_subject_events`] but stops after `limit` events.
+    pub fn iter_subject_events_limited<'a>(
+        &'a self,
+        path: &'a str,
+        limit: usize,
+    ) -> impl Iterator<Item = &'a Event> + 'a {
+        self.iter_subject_events(path).take(limit)
+    }
+
+    /// Like [`InMemDb::iter_subject_events`] but reports which segment of `path` doesn't exist,
+    /// so a typo'd path can be told apart from an empty subtree.
+    pub fn try_iter_subject_events<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> super::Result<impl Iterator<Item = &'a Event> + 'a> {
+        let subject = self.subjects.lookup(path).map_err(|(ancestor, missing)| {
+            Error::MissingSubjectSegment {
+                ancestor: ancestor.name().to_owned(),
+                missing: missing.to_owned(),
+            }
+        })?;
+
+        let subject_events = Subjects::all(subject).flat_map(|sub| sub.events().iter().copied());
+
+        Ok(IndexedEvents::new(
+            subject_events,
+            self.events.as_slice(),
+            &self.deleted,
+        ))
+    }
+
+    /// Feeds stored events to `f` in insertion order, without going through the query engine.
+    /// Only events under `subject` are replayed when one is given.
+    pub fn replay_into<F>(&self, subject: Option<&str>, f: F)
+    where
+        F: FnMut(&Event),
+    {
+        let offsets = if let Some(path) = subject {
+            let mut offsets = Subjects::new(path, &self.subjects)
+                .flat_map(|sub| sub.events().iter().copied())
+                .collect::<Vec<_>>();
+
+            offsets.sort_unstable();
+            offsets
+        } else {
+            (0..self.events.len()).collect()
+        };
+
+        IndexedEvents::new(offsets.into_iter(), self.events.as_slice(), &self.deleted).for_each(f);
     }
 
     pub fn iter_subjects<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
@@ -85,36 +298,161 @@ impl InMemDb {
         })
     }
 
-    pub fn run_query(&mut self, query: &str) -> super::Result<QueryProcessor<'_>> {
-        let query = self.session.parse(query)?;
-        let query = self.session.run_static_analysis(query)?;
+    /// Decodes payloads with the `datacontenttype` `content_type` using `decoder`, which turns the
+    /// raw bytes into JSON. Replaces the built-in decoder of that content type, if any.
+    pub fn register_decoder<F>(&mut self, content_type: impl Into<String>, decoder: F)
+    where
+        F: Fn(&[u8]) -> Option<serde_json::Value> + Send + Sync + 'static,
+    {
+        self.decoders.register(content_type, decoder);
 
-        Ok(query_plan(&self.session, self, query))
+        // cached projections may have been decoded differently
+        if let Some(cache) = &mut self.projections {
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+
+    /// Rejects appended events missing the attributes CloudEvents requires, see
+    /// [`Event::validate`]. Disabled by default.
+    pub fn set_event_validation(&mut self, enabled: bool) {
+        self.validate_events = enabled;
+    }
+
+    /// Keeps the projection of up to `capacity` events scanned by queries, keyed by the event
+    /// offset and the shape the query expects, so later queries reading the same shape skip
+    /// decoding its payload again. The oldest projections are evicted first. A `capacity` of 0
+    /// disables the cache and drops every cached projection.
+    pub fn set_projection_cache(&mut self, capacity: usize) {
+        self.projections = (capacity > 0).then(|| Mutex::new(ProjectionCache::new(capacity)));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cached_projections(&self) -> usize {
+        self.projections.as_ref().map_or(0, |cache| {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).len()
+        })
     }
-}
 
-impl Default for InMemDb {
-    fn default() -> Self {
-        Self {
-            types: Default::default(),
-            subjects: Default::default(),
-            events: vec![],
-            session: Session::builder().use_stdlib().build(),
+    fn project(
+        &self,
+        session: &Session,
+        offset: usize,
+        event: &Event,
+        shape: (Type, Option<usize>),
+    ) -> EvalResult<QueryValue> {
+        let (inferred_type, shape) = shape;
+        let (Some(cache), Some(shape)) = (&self.projections, shape) else {
+            return event.project(session, &self.decoders, inferred_type);
+        };
+
+        let cached = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(offset, shape)
+            .cloned();
+
+        if let Some(value) = cached {
+            return Ok(value);
         }
+
+        // decode outside the lock, concurrent queries only wait on lookups
+        let value = event.project(session, &self.decoders, inferred_type)?;
+        cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(offset, shape, value.clone());
+
+        Ok(value)
+    }
+
+    fn shape(&self, session: &Session, inferred_type: Type) -> (Type, Option<usize>) {
+        let shape = self.projections.as_ref().map(|cache| {
+            let key = TypeKey::new(session, inferred_type);
+            cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .shape(key)
+        });
+
+        (inferred_type, shape)
+    }
+
+    pub fn query_options_mut(&mut self) -> &mut QueryOptions {
+        &mut self.options
+    }
+
+    /// Content type given to appended events that don't come with one. Without it, such events
+    /// keep an empty content type and their payload can't be projected as a record.
+    pub fn set_default_content_type(&mut self, content_type: Option<String>) {
+        self.default_content_type = content_type;
+    }
+
+    /// Parses, type-checks and runs `query`. Only reads the database, so queries can run through
+    /// a shared reference, side by side.
+    pub fn run_query(&self, query: &str) -> super::Result<QueryProcessor<'_>> {
+        Ok(self.prepare(query)?.run(self))
+    }
+
+    /// Describes how `query` would run: its sources, whether it aggregates, and how its output
+    /// gets grouped, ordered and limited. Nothing gets executed.
+    pub fn explain(&self, query: &str) -> super::Result<QueryPlan> {
+        Ok(self.prepare(query)?.explain())
+    }
+
+    /// Parses and type-checks `query` once, so it can run many times without paying for either
+    /// again. The query options in effect now are the ones it runs with.
+    pub fn prepare(&self, query: &str) -> super::Result<PreparedQuery> {
+        let mut session = new_session();
+        let query = session.parse(query)?;
+        let query = session.run_static_analysis(query)?;
+
+        Ok(PreparedQuery {
+            session: Arc::new(session),
+            query,
+            options: self.options,
+        })
+    }
+}
+
+/// A query returned by [`InMemDb::prepare`], along with the session it got parsed in.
+#[derive(Clone)]
+pub struct PreparedQuery {
+    session: Arc<Session>,
+    query: Query<Typed>,
+    options: QueryOptions,
+}
+
+impl PreparedQuery {
+    /// Describes how the query runs, see [`InMemDb::explain`].
+    pub fn explain(&self) -> QueryPlan {
+        explain(&self.session, &self.query)
+    }
+
+    /// Runs the query against the events `db` holds right now. Only reads `db`, so several
+    /// queries can run side by side over the same shared database.
+    pub fn run<'a>(&self, db: &'a InMemDb) -> QueryProcessor<'a> {
+        query_plan(&self.session, db, self.options, self.query.clone())
     }
 }
 
 impl DataProvider for InMemDb {
     fn instantiate_named_data_source<'a>(
         &'a self,
-        name: &'a str,
+        session: &Arc<Session>,
+        name: &str,
         inferred_type: Type,
     ) -> Option<QueryProcessor<'a>> {
         if name.eq_ignore_ascii_case("events") {
+            let shape = self.shape(session, inferred_type);
+            let session = session.clone();
+
             Some(QueryProcessor::generic(
-                self.events
-                    .iter()
-                    .map(move |e| e.project(&self.session, inferred_type)),
+                IndexedEvents::new(0..self.events.len(), self.events.as_slice(), &self.deleted)
+                    .with_offsets()
+                    .map(move |(offset, e)| self.project(&session, offset, e, shape)),
             ))
         } else if name.eq_ignore_ascii_case("eventtypes") {
             Some(QueryProcessor::generic(self.types.keys().map(
@@ -125,19 +463,224 @@ impl DataProvider for InMemDb {
                 self.iter_subjects()
                     .map(|s| Ok(QueryValue::String(s.to_owned()))),
             ))
+        } else if name.eq_ignore_ascii_case("subjectcounts") {
+            Some(QueryProcessor::generic(
+                Subjects::all(&self.subjects).filter_map(|sub| {
+                    let count = sub
+                        .events()
+                        .iter()
+                        .filter(|offset| !self.deleted.contains(offset))
+                        .count();
+
+                    if count == 0 {
+                        return None;
+                    }
+
+                    let mut record = Record::new();
+                    record.insert(
+                        "subject".to_owned(),
+                        QueryValue::String(sub.name().to_owned()),
+                    );
+                    record.insert(
+                        "count".to_owned(),
+                        QueryValue::Number((count as f64).into()),
+                    );
+
+                    Some(Ok(QueryValue::Record(record)))
+                }),
+            ))
         } else {
             None
         }
     }
 
+    fn instantiate_types_data_source<'a>(
+        &'a self,
+        session: &Arc<Session>,
+        types: Vec<&str>,
+        inferred_type: Type,
+    ) -> Option<QueryProcessor<'a>> {
+        // keep events in insertion order, like a full scan would
+        let mut offsets = types
+            .into_iter()
+            .flat_map(|tpe| self.types.get(tpe).map(Vec::as_slice).unwrap_or_default())
+            .copied()
+            .collect::<Vec<_>>();
+
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let shape = self.shape(session, inferred_type);
+        let session = session.clone();
+
+        Some(QueryProcessor::generic(
+            IndexedEvents::new(offsets.into_iter(), self.events.as_slice(), &self.deleted)
+                .with_offsets()
+                .map(move |(offset, e)| self.project(&session, offset, e, shape)),
+        ))
+    }
+
     fn instantiate_subject_data_source<'a>(
         &'a self,
-        subject: &'a str,
+        session: &Arc<Session>,
+        subject: &str,
         inferred_type: Type,
     ) -> Option<QueryProcessor<'a>> {
+        let shape = self.shape(session, inferred_type);
+        let session = session.clone();
+        // the subject path only lives as long as the planning, resolve it now
+        let offsets = Subjects::new(subject, &self.subjects)
+            .flat_map(|sub| sub.events().iter().copied())
+            .collect::<Vec<_>>();
+
         Some(QueryProcessor::generic(
-            self.iter_subject_events(subject)
-                .map(move |e| e.project(&self.session, inferred_type)),
+            IndexedEvents::new(offsets.into_iter(), self.events.as_slice(), &self.deleted)
+                .with_offsets()
+                .map(move |(offset, e)| self.project(&session, offset, e, shape)),
         ))
     }
+
+    fn estimated_cardinality(&self, source: DataSource<'_>) -> Option<usize> {
+        let live = |offsets: &[usize]| {
+            offsets
+                .iter()
+                .filter(|offset| !self.deleted.contains(offset))
+                .count()
+        };
+
+        match source {
+            DataSource::Named(name) if name.eq_ignore_ascii_case("events") => {
+                Some(self.events.len() - self.deleted.len())
+            }
+
+            DataSource::Named(name) if name.eq_ignore_ascii_case("eventtypes") => {
+                Some(self.types.len())
+            }
+
+            DataSource::Named(_) => None,
+
+            DataSource::Types(types) => {
+                let types = types.iter().collect::<HashSet<_>>();
+
+                Some(
+                    types
+                        .into_iter()
+                        .filter_map(|tpe| self.types.get(*tpe))
+                        .map(|offsets| live(offsets))
+                        .sum(),
+                )
+            }
+
+            DataSource::Subject(subject) => Some(
+                Subjects::new(subject, &self.subjects)
+                    .map(|sub| live(sub.events()))
+                    .sum(),
+            ),
+        }
+    }
+}
+
+/// Session declaring every function the database knows on top of the standard library. Each
+/// query gets parsed in a fresh one, so preparing a query doesn't need exclusive access to the
+/// database.
+fn new_session() -> Session {
+    Session::builder()
+        .use_stdlib()
+        .declare_func("URL_ENCODE", vec![Type::String], Type::String)
+        .declare_func("URL_DECODE", vec![Type::String], Type::String)
+        .declare_func("HTML_ESCAPE", vec![Type::String], Type::String)
+        .declare_func("HTML_UNESCAPE", vec![Type::String], Type::String)
+        .declare_func("MD5", vec![Type::String], Type::String)
+        .declare_func("SHA256", vec![Type::String], Type::String)
+        .declare_func("CRC32", vec![Type::String], Type::Number)
+        // shift a DateTime or a Date, returning the same kind
+        .declare_func(
+            "DATE_ADD",
+            vec![Type::Unspecified, Type::Number, Type::String],
+            Type::Unspecified,
+        )
+        .declare_func(
+            "DATE_SUB",
+            vec![Type::Unspecified, Type::Number, Type::String],
+            Type::Unspecified,
+        )
+        .declare_func("PARSE_DATE", vec![Type::String, Type::String], Type::Date)
+        .declare_func(
+            "PARSE_DATETIME",
+            vec![Type::String, Type::String],
+            Type::DateTime,
+        )
+        // formats a DateTime, a Date or a Time
+        .declare_func(
+            "FORMAT_DATE",
+            vec![Type::Unspecified, Type::String],
+            Type::String,
+        )
+        // truncates a DateTime or a Date, always returning a DateTime
+        .declare_func(
+            "DATE_TRUNC",
+            vec![Type::String, Type::Unspecified],
+            Type::DateTime,
+        )
+        // two DateTimes or two Dates, checked when evaluated
+        .declare_func(
+            "DATE_DIFF",
+            vec![Type::Unspecified, Type::Unspecified, Type::String],
+            Type::Number,
+        )
+        .declare_func("ARRAY_LENGTH", vec![Type::Unspecified], Type::Number)
+        .declare_func(
+            "ELEMENT_AT",
+            vec![Type::Unspecified, Type::Number],
+            Type::Unspecified,
+        )
+        .declare_func(
+            "ARRAY_CONTAINS",
+            vec![Type::Unspecified, Type::Unspecified],
+            Type::Bool,
+        )
+        .declare_func("ARRAY_DISTINCT", vec![Type::Unspecified], Type::Unspecified)
+        .declare_func(
+            "SORT_ARRAY",
+            FunArgs {
+                values: vec![Type::Unspecified, Type::String],
+                needed: 1,
+            },
+            Type::Unspecified,
+        )
+        .declare_func("TYPEOF", vec![Type::Unspecified], Type::String)
+        .declare_func("IS_NULL", vec![Type::Unspecified], Type::Bool)
+        .declare_func("IS_NOT_NULL", vec![Type::Unspecified], Type::Bool)
+        .declare_func("TO_NUMBER", vec![Type::Unspecified], Type::Number)
+        .declare_func("TO_BOOL", vec![Type::Unspecified], Type::Bool)
+        .declare_func(
+            "BETWEEN",
+            vec![Type::Unspecified, Type::Unspecified, Type::Unspecified],
+            Type::Bool,
+        )
+        .declare_func(
+            "COALESCE",
+            vec![Type::Unspecified, Type::Unspecified],
+            Type::Unspecified,
+        )
+        .declare_agg_func("COUNT_IF", vec![Type::Bool], Type::Number)
+        .declare_agg_func("ARRAY_AGG", vec![Type::Unspecified], Type::Unspecified)
+        .declare_agg_func("STDDEV_POP", vec![Type::Number], Type::Number)
+        .declare_agg_func("STDDEV_SAMP", vec![Type::Number], Type::Number)
+        .declare_agg_func("VAR_POP", vec![Type::Number], Type::Number)
+        .declare_agg_func("VAR_SAMP", vec![Type::Number], Type::Number)
+        .declare_agg_func("COVAR", vec![Type::Number, Type::Number], Type::Number)
+        .declare_agg_func("CORR", vec![Type::Number, Type::Number], Type::Number)
+        .declare_agg_func(
+            "HISTOGRAM",
+            vec![Type::Number, Type::Number],
+            Type::Unspecified,
+        )
+        .declare_agg_func("UNIQUE_STRICT", vec![Type::Unspecified], Type::Unspecified)
+        .declare_agg_func(
+            "JSON_OBJECT_AGG",
+            vec![Type::Unspecified, Type::Unspecified],
+            Type::Unspecified,
+        )
+        .build()
 }
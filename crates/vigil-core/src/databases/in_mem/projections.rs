@@ -0,0 +1,123 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+};
+
+use eventql_parser::{Session, Type};
+
+use crate::values::QueryValue;
+
+/// Distinct shapes tracked before the cache starts over. Queries of an application tend to read
+/// a handful of shapes, running past it means shapes are built on the fly.
+const MAX_SHAPES: usize = 64;
+
+/// Structure of a type, field names included, independent of the session arena it was inferred
+/// in. Equivalent types inferred by different queries have the same key.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum TypeKey {
+    Scalar(mem::Discriminant<Type>),
+    Record(Vec<(String, TypeKey)>),
+    Array(Box<TypeKey>),
+    Custom(String),
+}
+
+impl TypeKey {
+    pub fn new(session: &Session, tpe: Type) -> Self {
+        match tpe {
+            Type::Record(rec) => Self::Record(
+                session
+                    .arena()
+                    .get_type_rec(rec)
+                    .iter()
+                    .map(|(name, field)| {
+                        (
+                            session.arena().get_str(*name).to_owned(),
+                            Self::new(session, *field),
+                        )
+                    })
+                    .collect(),
+            ),
+
+            Type::Array(inner) => Self::Array(Box::new(Self::new(
+                session,
+                session.arena().get_type(inner),
+            ))),
+
+            Type::Custom(name) => Self::Custom(session.arena().get_str(name).to_owned()),
+            tpe => Self::Scalar(mem::discriminant(&tpe)),
+        }
+    }
+}
+
+/// Projected events keyed by offset and shape. Holds at most `capacity` projections, the oldest
+/// one is evicted first.
+pub struct ProjectionCache {
+    capacity: usize,
+    shapes: HashMap<TypeKey, usize>,
+    next_shape: usize,
+    entries: HashMap<(usize, usize), QueryValue>,
+    order: VecDeque<(usize, usize)>,
+}
+
+impl ProjectionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            shapes: HashMap::new(),
+            next_shape: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Identifier of `key`, cheap to hash on every row.
+    pub fn shape(&mut self, key: TypeKey) -> usize {
+        if let Some(id) = self.shapes.get(&key) {
+            return *id;
+        }
+
+        if self.shapes.len() >= MAX_SHAPES {
+            self.clear();
+        }
+
+        // ids are never reused, a query still holding one from before a reset only misses
+        let id = self.next_shape;
+        self.next_shape += 1;
+        self.shapes.insert(key, id);
+
+        id
+    }
+
+    pub fn get(&self, offset: usize, shape: usize) -> Option<&QueryValue> {
+        self.entries.get(&(offset, shape))
+    }
+
+    pub fn insert(&mut self, offset: usize, shape: usize, value: QueryValue) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.insert((offset, shape), value).is_some() {
+            return;
+        }
+
+        self.order.push_back((offset, shape));
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+        self.entries.clear();
+        self.order.clear();
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::{HashMap, VecDeque},
+    mem,
     str::Split,
 };
 
@@ -19,10 +20,10 @@ impl Subject {
         self.events.as_slice()
     }
 
+    /// Events of the subject at `path`, created along the way if missing. Empty segments are
+    /// skipped, so `a//b/` is the same subject as `a/b`.
     pub fn entries<'a>(&mut self, mut path: impl Iterator<Item = &'a str>) -> &mut Vec<usize> {
-        let name = path.next().unwrap_or_default();
-
-        if !name.is_empty() {
+        if let Some(name) = path.find(|name| !name.trim().is_empty()) {
             return self
                 .nodes
                 .entry(name.to_owned())
@@ -43,16 +44,38 @@ impl Subject {
 
         &mut self.events
     }
+
+    /// Walks `path` down from this node. When a segment doesn't exist, returns the nearest existing
+    /// ancestor along with the missing segment instead.
+    pub fn lookup<'a>(&'a self, path: &'a str) -> Result<&'a Subject, (&'a Subject, &'a str)> {
+        let mut current = self;
+
+        for segment in path.split('/') {
+            if segment.trim().is_empty() {
+                continue;
+            }
+
+            current = current.nodes.get(segment).ok_or((current, segment))?;
+        }
+
+        Ok(current)
+    }
 }
 
 pub enum Subjects<'a> {
+    /// Follows `split` down the tree, skipping empty segments. A `*` segment matches every child,
+    /// so several subjects can be matched at once.
     Dive {
         split: Split<'a, char>,
-        current: &'a Subject,
+        matched: Vec<&'a Subject>,
+        max_depth: Option<usize>,
     },
 
+    /// Walks the subtrees of the queued subjects, breadth first. Each subject is queued with its
+    /// depth below the subject the walk started from.
     Browse {
-        queue: VecDeque<&'a Subject>,
+        queue: VecDeque<(&'a Subject, usize)>,
+        max_depth: Option<usize>,
     },
 }
 
@@ -60,13 +83,25 @@ impl<'a> Subjects<'a> {
     pub fn new(path: &'a str, subject: &'a Subject) -> Self {
         Self::Dive {
             split: path.split('/'),
-            current: subject,
+            matched: vec![subject],
+            max_depth: None,
+        }
+    }
+
+    /// Like [`Subjects::new`] but only goes `max_depth` levels below the subjects `path` matches:
+    /// `0` only yields those subjects, `1` their immediate children too, and so on.
+    pub fn new_with_depth(path: &'a str, subject: &'a Subject, max_depth: usize) -> Self {
+        Self::Dive {
+            split: path.split('/'),
+            matched: vec![subject],
+            max_depth: Some(max_depth),
         }
     }
 
     pub fn all(root: &'a Subject) -> Self {
         Self::Browse {
-            queue: VecDeque::from_iter([root]),
+            queue: VecDeque::from_iter([(root, 0)]),
+            max_depth: None,
         }
     }
 }
@@ -77,25 +112,50 @@ impl<'a> Iterator for Subjects<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             match self {
-                Subjects::Dive { split, current } => {
-                    let path = split.next().unwrap_or_default();
+                Subjects::Dive {
+                    split,
+                    matched,
+                    max_depth,
+                } => {
+                    let Some(path) = split.next() else {
+                        let queue = mem::take(matched).into_iter().map(|sub| (sub, 0)).collect();
+
+                        *self = Self::Browse {
+                            queue,
+                            max_depth: *max_depth,
+                        };
+                        continue;
+                    };
 
                     if path.trim().is_empty() {
-                        let mut queue = VecDeque::new();
-
-                        queue.push_back(*current);
-
-                        *self = Self::Browse { queue };
                         continue;
                     }
 
-                    *current = current.nodes.get(path)?;
+                    *matched = if path == "*" {
+                        matched.iter().flat_map(|sub| sub.nodes.values()).collect()
+                    } else {
+                        matched
+                            .iter()
+                            .filter_map(|sub| sub.nodes.get(path))
+                            .collect()
+                    };
+
+                    if matched.is_empty() {
+                        return None;
+                    }
                 }
 
-                Subjects::Browse { queue } => {
-                    let current = queue.pop_front()?;
+                Subjects::Browse { queue, max_depth } => {
+                    let (current, depth) = queue.pop_front()?;
+
+                    if max_depth.is_none_or(|max| depth < max) {
+                        queue.extend(current.nodes.values().map(|sub| (sub, depth + 1)));
+                    }
 
-                    queue.extend(current.nodes.values());
+                    // the root has no name, it only counts when events got appended to it
+                    if current.name.is_empty() && current.events.is_empty() {
+                        continue;
+                    }
 
                     return Some(current);
                 }
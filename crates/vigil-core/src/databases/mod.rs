@@ -10,6 +10,15 @@ pub enum Error {
 
     #[error("subject cannot start with a '/'")]
     IllegalSubject,
+
+    #[error("subject segment '{missing}' doesn't exist under '{ancestor}'")]
+    MissingSubjectSegment { ancestor: String, missing: String },
+
+    #[error("malformed NDJSON at line {line}: {reason}")]
+    MalformedNdjson { line: usize, reason: String },
+
+    #[error("invalid event: {0}")]
+    InvalidEvent(String),
 }
 
 impl From<eventql_parser::prelude::Error> for Error {
@@ -1,27 +1,99 @@
-use std::{
-    borrow::Cow,
-    cmp::Ordering,
-    collections::{BTreeMap, HashMap},
-    f64,
-};
+use std::{borrow::Cow, cmp::Ordering, collections::HashMap, f64, fmt::Write, sync::Arc};
 
-use chrono::{Datelike, Timelike, Utc};
+use chrono::{
+    DateTime, Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike, Utc,
+};
 use eventql_parser::prelude::{Operator, Typed};
 use eventql_parser::{Query, Session, StrRef};
 use rand::Rng;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
-use crate::values::QueryValue;
+use crate::values::{QueryValue, Record};
 
 #[derive(Debug, Error, Serialize)]
 pub enum EvalError {
     #[error("runtime error: {0}")]
     Runtime(Cow<'static, str>),
+
+    #[error("query pulled more rows than its row budget allows")]
+    RowBudgetExceeded,
+
+    #[error("query was cancelled")]
+    Cancelled,
 }
 
 pub type EvalResult<A> = std::result::Result<A, EvalError>;
 
+/// Minimum and maximum argument counts of the builtins indexing their arguments. Checked before
+/// dispatching so a call with missing arguments fails instead of panicking.
+const BUILTIN_ARITIES: &[(&str, usize, usize)] = &[
+    ("if", 3, 3),
+    // math
+    ("abs", 1, 1),
+    ("ceil", 1, 1),
+    ("floor", 1, 1),
+    ("round", 1, 1),
+    ("cos", 1, 1),
+    ("sin", 1, 1),
+    ("tan", 1, 1),
+    ("exp", 1, 1),
+    ("pow", 2, 2),
+    ("sqrt", 1, 1),
+    ("rand", 0, 0),
+    ("pi", 0, 0),
+    // strings
+    ("lower", 1, 1),
+    ("upper", 1, 1),
+    ("trim", 1, 1),
+    ("ltrim", 1, 1),
+    ("rtrim", 1, 1),
+    ("len", 1, 1),
+    ("instr", 2, 2),
+    ("substring", 3, 3),
+    ("replace", 3, 3),
+    ("startswith", 2, 2),
+    ("endswith", 2, 2),
+    // encoding and hashing
+    ("url_encode", 1, 1),
+    ("url_decode", 1, 1),
+    ("html_escape", 1, 1),
+    ("html_unescape", 1, 1),
+    ("md5", 1, 1),
+    ("sha256", 1, 1),
+    ("crc32", 1, 1),
+    // dates and times
+    ("now", 0, 0),
+    ("year", 1, 1),
+    ("month", 1, 1),
+    ("day", 1, 1),
+    ("hour", 1, 1),
+    ("minute", 1, 1),
+    ("second", 1, 1),
+    ("weekday", 1, 1),
+    ("date_add", 3, 3),
+    ("date_sub", 3, 3),
+    ("parse_date", 2, 2),
+    ("parse_datetime", 2, 2),
+    ("format_date", 2, 2),
+    ("date_trunc", 2, 2),
+    ("date_diff", 3, 3),
+    // arrays
+    ("array_length", 1, 1),
+    ("element_at", 2, 2),
+    ("array_contains", 2, 2),
+    ("array_distinct", 1, 1),
+    ("sort_array", 1, 2),
+    // types and comparisons
+    ("typeof", 1, 1),
+    ("is_null", 1, 1),
+    ("is_not_null", 1, 1),
+    ("to_number", 1, 1),
+    ("to_bool", 1, 1),
+    ("between", 3, 3),
+];
+
 impl QueryValue {
     fn as_bool(&self) -> EvalResult<bool> {
         if let Self::Bool(b) = self {
@@ -34,19 +106,27 @@ impl QueryValue {
     }
 }
 
-pub struct Interpreter<'a> {
-    pub(crate) session: &'a Session,
+pub struct Interpreter {
+    pub(crate) session: Arc<Session>,
     env: HashMap<StrRef, QueryValue>,
+    now: DateTime<Utc>,
 }
 
-impl<'a> Interpreter<'a> {
-    pub fn new(session: &'a Session) -> Self {
+impl Interpreter {
+    pub fn new(session: Arc<Session>) -> Self {
         Self {
             session,
             env: Default::default(),
+            now: Utc::now(),
         }
     }
 
+    /// Pins the instant every `now()` call returns, which otherwise is when the interpreter got
+    /// created. Queries pin it to [`QueryOptions::now`](crate::queries::QueryOptions::now).
+    pub fn with_now(self, now: DateTime<Utc>) -> Self {
+        Self { now, ..self }
+    }
+
     pub fn env_mut(&mut self) -> &mut HashMap<StrRef, QueryValue> {
         self.env.clear();
         &mut self.env
@@ -63,10 +143,32 @@ impl<'a> Interpreter<'a> {
         match value {
             QueryValue::Null => Ok(QueryValue::Null),
 
+            // strings parse the way payload fields of those types do, an unparsable string
+            // fails the conversion rather than silently turning into `Null`
             QueryValue::String(cow) => match tpe {
                 eventql_parser::Type::String | eventql_parser::Type::Subject => {
                     Ok(QueryValue::String(cow.clone()))
                 }
+
+                eventql_parser::Type::Number => parse_number(cow)
+                    .map(|n| QueryValue::Number(n.into()))
+                    .ok_or_else(|| unparsable(cow, "Number")),
+
+                eventql_parser::Type::Date => cow
+                    .parse::<NaiveDate>()
+                    .map(QueryValue::Date)
+                    .map_err(|_| unparsable(cow, "Date")),
+
+                eventql_parser::Type::Time => cow
+                    .parse::<NaiveTime>()
+                    .map(QueryValue::Time)
+                    .map_err(|_| unparsable(cow, "Time")),
+
+                eventql_parser::Type::DateTime => cow
+                    .parse::<DateTime<Utc>>()
+                    .map(QueryValue::DateTime)
+                    .map_err(|_| unparsable(cow, "DateTime")),
+
                 _ => Err(EvalError::Runtime(
                     format!(
                         "cannot convert String to {}",
@@ -140,6 +242,33 @@ impl<'a> Interpreter<'a> {
         match (a, b) {
             (QueryValue::Null, QueryValue::Null) => Ok(QueryValue::Null),
 
+            // like SQL, comparing anything with Null is unknown rather than an error
+            (QueryValue::Null, _) | (_, QueryValue::Null)
+                if matches!(
+                    op,
+                    Operator::Eq
+                        | Operator::Neq
+                        | Operator::Lt
+                        | Operator::Lte
+                        | Operator::Gt
+                        | Operator::Gte
+                ) =>
+            {
+                Ok(QueryValue::Null)
+            }
+
+            // three-valued logic: false AND unknown is false, true OR unknown is true and anything
+            // else involving an unknown stays unknown
+            (QueryValue::Null, QueryValue::Bool(b)) | (QueryValue::Bool(b), QueryValue::Null)
+                if matches!(op, Operator::And | Operator::Or | Operator::Xor) =>
+            {
+                Ok(match (op, b) {
+                    (Operator::And, false) => QueryValue::Bool(false),
+                    (Operator::Or, true) => QueryValue::Bool(true),
+                    _ => QueryValue::Null,
+                })
+            }
+
             (QueryValue::String(a), QueryValue::String(b)) => match op {
                 Operator::Eq => Ok(QueryValue::Bool(a == b)),
                 Operator::Neq => Ok(QueryValue::Bool(a != b)),
@@ -193,8 +322,15 @@ impl<'a> Interpreter<'a> {
                         return Ok(QueryValue::Bool(false));
                     }
 
-                    for ((a_k, a_v), (b_k, b_v)) in a.iter().zip(b.iter()) {
-                        if a_k != b_k || self.eval_binary(Operator::Eq, a_v, b_v)?.as_bool()? {
+                    for (a_k, a_v) in a.iter() {
+                        let Some(b_v) = b.get(a_k) else {
+                            return Ok(QueryValue::Bool(false));
+                        };
+
+                        if !matches!(
+                            self.eval_binary(Operator::Eq, a_v, b_v)?,
+                            QueryValue::Bool(true)
+                        ) {
                             return Ok(QueryValue::Bool(false));
                         }
                     }
@@ -218,7 +354,10 @@ impl<'a> Interpreter<'a> {
                     }
 
                     for (a, b) in a.iter().zip(b.iter()) {
-                        if !self.eval_binary(Operator::Eq, a, b)?.as_bool()? {
+                        if !matches!(
+                            self.eval_binary(Operator::Eq, a, b)?,
+                            QueryValue::Bool(true)
+                        ) {
                             return Ok(QueryValue::Bool(false));
                         }
                     }
@@ -230,6 +369,19 @@ impl<'a> Interpreter<'a> {
                     !self.eval_binary(Operator::Eq, this, that)?.as_bool()?,
                 )),
 
+                Operator::Lt | Operator::Lte | Operator::Gt | Operator::Gte => {
+                    Ok(match this.strict_partial_cmp(that) {
+                        Some(ordering) => QueryValue::Bool(match op {
+                            Operator::Lt => ordering.is_lt(),
+                            Operator::Lte => ordering.is_le(),
+                            Operator::Gt => ordering.is_gt(),
+                            _ => ordering.is_ge(),
+                        }),
+
+                        None => QueryValue::Null,
+                    })
+                }
+
                 _ => Err(EvalError::Runtime(
                     format!("unsupported operator {op} for Array").into(),
                 )),
@@ -273,13 +425,32 @@ impl<'a> Interpreter<'a> {
 
             (QueryValue::Array(values), value) if matches!(op, Operator::Contains) => {
                 for a in values.iter() {
-                    if self.eval_binary(Operator::Eq, a, value)?.as_bool()? {
+                    if matches!(
+                        self.eval_binary(Operator::Eq, a, value)?,
+                        QueryValue::Bool(true)
+                    ) {
                         return Ok(QueryValue::Bool(true));
                     }
                 }
                 Ok(QueryValue::Bool(false))
             }
 
+            // `x IN [a, b, c]`, with the array on the right
+            (_, QueryValue::Array(_)) if matches!(op, Operator::Contains) => {
+                self.eval_binary(Operator::Contains, b, a)
+            }
+
+            // values of different kinds can't be ordered, which is unknown rather than a
+            // misleading boolean
+            (a, b)
+                if matches!(
+                    op,
+                    Operator::Lt | Operator::Lte | Operator::Gt | Operator::Gte
+                ) && a.strict_partial_cmp(b).is_none() =>
+            {
+                Ok(QueryValue::Null)
+            }
+
             _ => Err(EvalError::Runtime(
                 format!("unsupported binary operation {op} for given types").into(),
             )),
@@ -311,6 +482,8 @@ impl<'a> Interpreter<'a> {
             Operator::Not => {
                 if let QueryValue::Bool(b) = value {
                     Ok(QueryValue::Bool(!b))
+                } else if let QueryValue::Null = value {
+                    Ok(QueryValue::Null)
                 } else {
                     Err(EvalError::Runtime(
                         "unary ! operator requires a boolean".into(),
@@ -326,7 +499,11 @@ impl<'a> Interpreter<'a> {
 
     pub fn eval_predicate(&self, query: &Query<Typed>) -> EvalResult<bool> {
         if let Some(predicate) = query.predicate.as_ref().copied() {
-            return self.eval_expr(predicate)?.as_bool();
+            // an unknown (Null) predicate doesn't match
+            return match self.eval_expr(predicate)? {
+                QueryValue::Null => Ok(false),
+                value => value.as_bool(),
+            };
         }
 
         Ok(true)
@@ -357,7 +534,7 @@ impl<'a> Interpreter<'a> {
 
             eventql_parser::Value::Record(fields) => {
                 let fields = self.session.arena().get_rec(fields);
-                let mut record = BTreeMap::new();
+                let mut record = Record::new();
 
                 for field in fields {
                     record.insert(
@@ -384,13 +561,68 @@ impl<'a> Interpreter<'a> {
 
             eventql_parser::Value::App(app) => {
                 let fun_args = self.session.arena().get_vec(app.args);
+                let fun_name = self.session.arena().get_str(app.func);
+
+                if let Some((name, min, max)) = BUILTIN_ARITIES
+                    .iter()
+                    .find(|(name, _, _)| name.eq_ignore_ascii_case(fun_name))
+                    && !(*min..=*max).contains(&fun_args.len())
+                {
+                    let expected = if min == max {
+                        min.to_string()
+                    } else {
+                        format!("{min} to {max}")
+                    };
+
+                    return Err(EvalError::Runtime(
+                        format!("{name} expects {expected} arguments").into(),
+                    ));
+                }
+
+                // --------------
+                // Conditional functions
+                // --------------
+                // only the arguments they end up picking get evaluated
+
+                if fun_name.eq_ignore_ascii_case("if")
+                    && let [cond, then, otherwise] = fun_args
+                {
+                    // an unknown (Null) condition doesn't hold, like a predicate
+                    let branch = match self.eval_expr(*cond)? {
+                        QueryValue::Bool(true) => then,
+                        QueryValue::Bool(false) | QueryValue::Null => otherwise,
+                        value => {
+                            return Err(EvalError::Runtime(
+                                format!(
+                                    "if() requires a bool condition, got {}",
+                                    type_name(&value)
+                                )
+                                .into(),
+                            ));
+                        }
+                    };
+
+                    return self.eval_expr(*branch);
+                }
+
+                if fun_name.eq_ignore_ascii_case("coalesce") {
+                    for arg in fun_args {
+                        let value = self.eval_expr(*arg)?;
+
+                        if !matches!(value, QueryValue::Null) {
+                            return Ok(value);
+                        }
+                    }
+
+                    return Ok(QueryValue::Null);
+                }
+
                 let mut args = Vec::with_capacity(fun_args.len());
 
                 for arg in fun_args {
                     args.push(self.eval(self.session.arena().get_expr(*arg).value)?);
                 }
 
-                let fun_name = self.session.arena().get_str(app.func);
                 // -------------
                 // Math functions
                 // ------------
@@ -549,12 +781,71 @@ impl<'a> Interpreter<'a> {
                     return Ok(QueryValue::Bool(x.ends_with(y)));
                 }
 
+                // -------------
+                // Encoding functions
+                // -------------
+
+                if fun_name.eq_ignore_ascii_case("url_encode")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::String(urlencoding::encode(s).into_owned()));
+                }
+
+                if fun_name.eq_ignore_ascii_case("url_decode")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    // malformed percent-encoding (invalid UTF-8 once decoded) yields Null
+                    return Ok(urlencoding::decode(s)
+                        .map(|s| QueryValue::String(s.into_owned()))
+                        .unwrap_or(QueryValue::Null));
+                }
+
+                if fun_name.eq_ignore_ascii_case("html_escape")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::String(
+                        html_escape::encode_quoted_attribute(s).into_owned(),
+                    ));
+                }
+
+                if fun_name.eq_ignore_ascii_case("html_unescape")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::String(
+                        html_escape::decode_html_entities(s).into_owned(),
+                    ));
+                }
+
+                // -------------
+                // Hash functions
+                // -------------
+
+                if fun_name.eq_ignore_ascii_case("md5")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::String(format!("{:x}", md5::compute(s))));
+                }
+
+                if fun_name.eq_ignore_ascii_case("sha256")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::String(format!("{:x}", Sha256::digest(s))));
+                }
+
+                if fun_name.eq_ignore_ascii_case("crc32")
+                    && let QueryValue::String(s) = &args[0]
+                {
+                    return Ok(QueryValue::Number(
+                        (crc32fast::hash(s.as_bytes()) as f64).into(),
+                    ));
+                }
+
                 // -------------
                 // Date and Time functions
                 // -------------
 
                 if fun_name.eq_ignore_ascii_case("now") {
-                    return Ok(QueryValue::DateTime(Utc::now()));
+                    return Ok(QueryValue::DateTime(self.now));
                 }
 
                 if fun_name.eq_ignore_ascii_case("year") {
@@ -637,19 +928,228 @@ impl<'a> Interpreter<'a> {
                     };
                 }
 
+                if fun_name.eq_ignore_ascii_case("date_add")
+                    && let QueryValue::Number(amount) = &args[1]
+                    && let QueryValue::String(unit) = &args[2]
+                {
+                    return shift_temporal(&temporal_arg(&args[0]), interval(amount.0)?, unit);
+                }
+
+                if fun_name.eq_ignore_ascii_case("date_sub")
+                    && let QueryValue::Number(amount) = &args[1]
+                    && let QueryValue::String(unit) = &args[2]
+                {
+                    return shift_temporal(&temporal_arg(&args[0]), -interval(amount.0)?, unit);
+                }
+
+                if fun_name.eq_ignore_ascii_case("parse_date")
+                    && let QueryValue::String(s) = &args[0]
+                    && let QueryValue::String(fmt) = &args[1]
+                {
+                    // lenient like JSON coercion: unparseable input is Null rather than an error
+                    return Ok(NaiveDate::parse_from_str(s, fmt)
+                        .map(QueryValue::Date)
+                        .unwrap_or(QueryValue::Null));
+                }
+
+                if fun_name.eq_ignore_ascii_case("parse_datetime")
+                    && let QueryValue::String(s) = &args[0]
+                    && let QueryValue::String(fmt) = &args[1]
+                {
+                    // formats without an offset are interpreted as UTC
+                    return Ok(DateTime::parse_from_str(s, fmt)
+                        .map(|t| t.to_utc())
+                        .or_else(|_| NaiveDateTime::parse_from_str(s, fmt).map(|t| t.and_utc()))
+                        .map(QueryValue::DateTime)
+                        .unwrap_or(QueryValue::Null));
+                }
+
+                if fun_name.eq_ignore_ascii_case("format_date")
+                    && let QueryValue::String(fmt) = &args[1]
+                {
+                    let mut out = String::new();
+                    let res = match temporal_arg(&args[0]).as_ref() {
+                        QueryValue::DateTime(t) => write!(out, "{}", t.format(fmt)),
+                        QueryValue::Date(d) => write!(out, "{}", d.format(fmt)),
+                        QueryValue::Time(t) => write!(out, "{}", t.format(fmt)),
+                        _ => {
+                            return Err(EvalError::Runtime(
+                                "format_date() requires a DateTime, Date or Time argument".into(),
+                            ));
+                        }
+                    };
+
+                    // chrono reports unsupported specifiers only when rendering
+                    return res.map(|_| QueryValue::String(out)).map_err(|_| {
+                        EvalError::Runtime(format!("invalid date format: {fmt}").into())
+                    });
+                }
+
+                if fun_name.eq_ignore_ascii_case("date_trunc")
+                    && let QueryValue::String(unit) = &args[0]
+                {
+                    return match temporal_arg(&args[1]).as_ref() {
+                        QueryValue::DateTime(t) => truncate_datetime(unit, t),
+                        QueryValue::Date(d) => {
+                            truncate_datetime(unit, &d.and_time(NaiveTime::MIN).and_utc())
+                        }
+                        _ => Err(EvalError::Runtime(
+                            "date_trunc() requires a DateTime or Date argument".into(),
+                        )),
+                    };
+                }
+
+                if fun_name.eq_ignore_ascii_case("date_diff")
+                    && let QueryValue::String(unit) = &args[2]
+                {
+                    return temporal_diff(&temporal_arg(&args[0]), &temporal_arg(&args[1]), unit);
+                }
+
                 // --------------
-                // Conditional functions
+                // Array functions
                 // --------------
 
-                if fun_name.eq_ignore_ascii_case("if")
-                    && let QueryValue::Bool(b) = args[0]
+                if fun_name.eq_ignore_ascii_case("array_length")
+                    && let QueryValue::Array(values) = &args[0]
+                {
+                    return Ok(QueryValue::Number((values.len() as f64).into()));
+                }
+
+                if fun_name.eq_ignore_ascii_case("element_at")
+                    && let QueryValue::Array(values) = &args[0]
+                    && let QueryValue::Number(index) = &args[1]
+                {
+                    // 1-based, anything out of bounds or fractional is Null
+                    let element = (index.fract() == 0.0 && index.0 >= 1.0)
+                        .then(|| values.get(index.0 as usize - 1))
+                        .flatten();
+
+                    return Ok(element.cloned().unwrap_or(QueryValue::Null));
+                }
+
+                if fun_name.eq_ignore_ascii_case("array_contains")
+                    && let QueryValue::Array(values) = &args[0]
+                {
+                    return Ok(QueryValue::Bool(values.contains(&args[1])));
+                }
+
+                if fun_name.eq_ignore_ascii_case("array_distinct")
+                    && let QueryValue::Array(values) = &args[0]
+                {
+                    // keeps the first occurrence of each value
+                    let mut distinct = Vec::with_capacity(values.len());
+                    for value in values {
+                        if !distinct.contains(value) {
+                            distinct.push(value.clone());
+                        }
+                    }
+
+                    return Ok(QueryValue::Array(distinct));
+                }
+
+                if fun_name.eq_ignore_ascii_case("sort_array")
+                    && let QueryValue::Array(values) = &args[0]
                 {
-                    // TODO - cloning is not necessary here as we could evaluate args lazily but that'll do for now
-                    return Ok(if b { args[1].clone() } else { args[2].clone() });
+                    // total order, so NaN consistently sorts after every other number
+                    let mut sorted = values.clone();
+                    sorted.sort();
+
+                    match args.get(1) {
+                        None => {}
+                        Some(QueryValue::String(order)) if order.eq_ignore_ascii_case("asc") => {}
+                        Some(QueryValue::String(order)) if order.eq_ignore_ascii_case("desc") => {
+                            sorted.reverse()
+                        }
+
+                        Some(_) => {
+                            return Err(EvalError::Runtime(
+                                "sort_array() order must be either \"asc\" or \"desc\"".into(),
+                            ));
+                        }
+                    }
+
+                    return Ok(QueryValue::Array(sorted));
+                }
+
+                // --------------
+                // Type functions
+                // --------------
+
+                if fun_name.eq_ignore_ascii_case("typeof") {
+                    return Ok(QueryValue::String(type_name(&args[0]).to_owned()));
                 }
 
+                if fun_name.eq_ignore_ascii_case("is_null") {
+                    return Ok(QueryValue::Bool(matches!(args[0], QueryValue::Null)));
+                }
+
+                if fun_name.eq_ignore_ascii_case("is_not_null") {
+                    return Ok(QueryValue::Bool(!matches!(args[0], QueryValue::Null)));
+                }
+
+                if fun_name.eq_ignore_ascii_case("to_number") {
+                    return Ok(match &args[0] {
+                        QueryValue::Number(n) => QueryValue::Number(*n),
+                        QueryValue::String(s) => parse_number(s)
+                            .map_or(QueryValue::Null, |n| QueryValue::Number(n.into())),
+                        _ => QueryValue::Null,
+                    });
+                }
+
+                if fun_name.eq_ignore_ascii_case("to_bool") {
+                    return Ok(match &args[0] {
+                        QueryValue::Bool(b) => QueryValue::Bool(*b),
+                        QueryValue::String(s) => match s.trim().to_ascii_lowercase().as_str() {
+                            "true" | "1" => QueryValue::Bool(true),
+                            "false" | "0" => QueryValue::Bool(false),
+                            _ => QueryValue::Null,
+                        },
+                        _ => QueryValue::Null,
+                    });
+                }
+
+                // --------------
+                // Comparison functions
+                // --------------
+
+                if fun_name.eq_ignore_ascii_case("between") {
+                    let (value, low, high) = (&args[0], &args[1], &args[2]);
+
+                    if [value, low, high]
+                        .iter()
+                        .any(|arg| matches!(arg, QueryValue::Null))
+                    {
+                        return Ok(QueryValue::Null);
+                    }
+
+                    if value.strict_partial_cmp(low).is_none()
+                        || value.strict_partial_cmp(high).is_none()
+                    {
+                        return Err(EvalError::Runtime(
+                            "between() bounds must have the same type as the value".into(),
+                        ));
+                    }
+
+                    let above = self.eval_binary(Operator::Gte, value, low)?;
+                    let below = self.eval_binary(Operator::Lte, value, high)?;
+
+                    return self.eval_binary(Operator::And, &above, &below);
+                }
+
+                let Some(signature) = self.session.global_scope().get(app.func) else {
+                    return Err(EvalError::Runtime(
+                        format!("unknown function: {fun_name}").into(),
+                    ));
+                };
+
+                let got = args.iter().map(type_name).collect::<Vec<_>>().join(", ");
+
                 Err(EvalError::Runtime(
-                    format!("unknown function or invalid arguments: {fun_name}").into(),
+                    format!(
+                        "{fun_name}() can't be called with ({got}), expected {}",
+                        self.session.display_type(signature)
+                    )
+                    .into(),
                 ))
             }
 
@@ -684,3 +1184,185 @@ impl<'a> Interpreter<'a> {
         }
     }
 }
+
+/// Runtime type of `value`, as reported by `typeof()`.
+fn type_name(value: &QueryValue) -> &'static str {
+    match value {
+        QueryValue::Null => "null",
+        QueryValue::String(_) => "string",
+        QueryValue::Number(_) => "number",
+        QueryValue::Bool(_) => "bool",
+        QueryValue::Record(_) => "record",
+        QueryValue::Array(_) => "array",
+        QueryValue::DateTime(_) => "datetime",
+        QueryValue::Date(_) => "date",
+        QueryValue::Time(_) => "time",
+    }
+}
+
+/// Reads a string argument as the `DateTime` or `Date` it holds. Arguments accepting either kind
+/// aren't typed by the checker, so a payload field reaches the function as a plain string.
+fn temporal_arg(value: &QueryValue) -> Cow<'_, QueryValue> {
+    if let QueryValue::String(s) = value {
+        if let Ok(t) = s.parse::<DateTime<Utc>>() {
+            return Cow::Owned(QueryValue::DateTime(t));
+        }
+
+        if let Ok(d) = s.parse::<NaiveDate>() {
+            return Cow::Owned(QueryValue::Date(d));
+        }
+    }
+
+    Cow::Borrowed(value)
+}
+
+/// Amount of a `date_add()` or `date_sub()` call, which has to be a whole number.
+fn interval(amount: f64) -> EvalResult<i64> {
+    // `i64::MAX as f64` rounds up to 2^63, which doesn't fit in an `i64`
+    if amount.fract() != 0.0 || amount.abs() >= i64::MAX as f64 {
+        return Err(EvalError::Runtime(
+            format!("date_add() and date_sub() require an integer amount, got {amount}").into(),
+        ));
+    }
+
+    Ok(amount as i64)
+}
+
+/// Shifts a `DateTime` or a `Date` by `amount` units. Calendar units (months and years) go through
+/// chrono's `Months`, clamping the day of month when needed (e.g. Jan 31 + 1 month = Feb 28/29).
+fn shift_temporal(value: &QueryValue, amount: i64, unit: &str) -> EvalResult<QueryValue> {
+    let overflow = || EvalError::Runtime("date arithmetic overflow".into());
+    let unit = unit.to_ascii_lowercase();
+
+    if matches!(unit.as_str(), "month" | "months" | "year" | "years") {
+        let months = if unit.starts_with("year") {
+            amount.checked_mul(12).ok_or_else(overflow)?
+        } else {
+            amount
+        };
+
+        let delta = Months::new(u32::try_from(months.unsigned_abs()).map_err(|_| overflow())?);
+
+        return match value {
+            QueryValue::DateTime(t) => if months >= 0 {
+                t.checked_add_months(delta)
+            } else {
+                t.checked_sub_months(delta)
+            }
+            .map(QueryValue::DateTime)
+            .ok_or_else(overflow),
+
+            QueryValue::Date(d) => if months >= 0 {
+                d.checked_add_months(delta)
+            } else {
+                d.checked_sub_months(delta)
+            }
+            .map(QueryValue::Date)
+            .ok_or_else(overflow),
+
+            _ => Err(EvalError::Runtime(
+                "date_add() and date_sub() require a DateTime or Date argument".into(),
+            )),
+        };
+    }
+
+    let delta = match unit.as_str() {
+        "second" | "seconds" => TimeDelta::try_seconds(amount),
+        "minute" | "minutes" => TimeDelta::try_minutes(amount),
+        "hour" | "hours" => TimeDelta::try_hours(amount),
+        "day" | "days" => TimeDelta::try_days(amount),
+        "week" | "weeks" => TimeDelta::try_weeks(amount),
+        _ => {
+            return Err(EvalError::Runtime(
+                format!("unknown interval unit: {unit}").into(),
+            ));
+        }
+    }
+    .ok_or_else(overflow)?;
+
+    match value {
+        QueryValue::DateTime(t) => t
+            .checked_add_signed(delta)
+            .map(QueryValue::DateTime)
+            .ok_or_else(overflow),
+
+        QueryValue::Date(d) if delta.subsec_nanos() == 0 && delta.num_seconds() % 86_400 == 0 => d
+            .checked_add_signed(delta)
+            .map(QueryValue::Date)
+            .ok_or_else(overflow),
+
+        QueryValue::Date(_) => Err(EvalError::Runtime(
+            format!("cannot shift a Date by {unit}").into(),
+        )),
+
+        _ => Err(EvalError::Runtime(
+            "date_add() and date_sub() require a DateTime or Date argument".into(),
+        )),
+    }
+}
+
+/// Returns `a - b` expressed in the given unit. Only fixed-length units are supported since months
+/// and years don't map to a constant duration.
+fn temporal_diff(a: &QueryValue, b: &QueryValue, unit: &str) -> EvalResult<QueryValue> {
+    let delta = match (a, b) {
+        (QueryValue::DateTime(a), QueryValue::DateTime(b)) => a.signed_duration_since(*b),
+        (QueryValue::Date(a), QueryValue::Date(b)) => a.signed_duration_since(*b),
+        _ => {
+            return Err(EvalError::Runtime(
+                "date_diff() requires two DateTime or two Date arguments".into(),
+            ));
+        }
+    };
+
+    let unit = unit.to_ascii_lowercase();
+    let seconds_per_unit = match unit.as_str() {
+        "second" | "seconds" => 1f64,
+        "minute" | "minutes" => 60f64,
+        "hour" | "hours" => 3_600f64,
+        "day" | "days" => 86_400f64,
+        "week" | "weeks" => 604_800f64,
+        _ => {
+            return Err(EvalError::Runtime(
+                format!("unsupported date_diff() unit: {unit}").into(),
+            ));
+        }
+    };
+
+    let seconds = delta.num_seconds() as f64 + delta.subsec_nanos() as f64 / 1e9;
+
+    Ok(QueryValue::Number((seconds / seconds_per_unit).into()))
+}
+
+/// Parses a string holding a finite number, surrounding whitespace allowed.
+fn parse_number(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Error of a string that can't be read as a value of type `tpe`.
+fn unparsable(s: &str, tpe: &str) -> EvalError {
+    EvalError::Runtime(format!("cannot convert String '{s}' to {tpe}").into())
+}
+
+/// Zeroes out every component of `t` smaller than `unit`.
+fn truncate_datetime(unit: &str, t: &DateTime<Utc>) -> EvalResult<QueryValue> {
+    let unit = unit.to_ascii_lowercase();
+    let date = t.date_naive();
+    let truncated = match unit.as_str() {
+        "year" => NaiveDate::from_ymd_opt(date.year(), 1, 1).map(|d| d.and_time(NaiveTime::MIN)),
+        "month" => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)
+            .map(|d| d.and_time(NaiveTime::MIN)),
+        "day" => Some(date.and_time(NaiveTime::MIN)),
+        "hour" => date.and_hms_opt(t.hour(), 0, 0),
+        "minute" => date.and_hms_opt(t.hour(), t.minute(), 0),
+        "second" => date.and_hms_opt(t.hour(), t.minute(), t.second()),
+        _ => {
+            return Err(EvalError::Runtime(
+                format!("unsupported date_trunc() unit: {unit}").into(),
+            ));
+        }
+    };
+
+    truncated
+        .map(|t| QueryValue::DateTime(t.and_utc()))
+        .ok_or_else(|| EvalError::Runtime("invalid date in date_trunc()".into()))
+}
@@ -1,75 +1,295 @@
-use eventql_parser::{Query, Session, Type, prelude::Typed};
+use std::sync::Arc;
 
-use crate::queries::{QueryProcessor, Sources, aggregates::AggQuery, events::EventQuery};
+use chrono::Utc;
+use eventql_parser::{
+    ExprRef, Limit, Order, Query, Session, SourceKind, StrRef, Type, Value,
+    prelude::{Operator, Typed},
+};
 
+use crate::queries::{
+    CancellationHandle, PlanLimit, PlanOrder, QueryOptions, QueryPlan, QueryProcessor, RowBudget,
+    SourcePlan, Sources,
+    aggregates::AggQuery,
+    events::{EventQuery, is_rand_call},
+};
+
+/// Sources queries read from. `inferred_type` is the shape the query expects rows to have, it is
+/// interned in `session`, the session the query got parsed in.
 pub trait DataProvider {
     fn instantiate_named_data_source<'a>(
         &'a self,
-        name: &'a str,
+        session: &Arc<Session>,
+        name: &str,
         inferred_type: Type,
     ) -> Option<QueryProcessor<'a>>;
 
     fn instantiate_subject_data_source<'a>(
         &'a self,
-        subject: &'a str,
+        session: &Arc<Session>,
+        subject: &str,
         inferred_type: Type,
     ) -> Option<QueryProcessor<'a>>;
+
+    /// Events restricted to the given event types, used in place of the `events` source when the
+    /// query predicate only lets those types through. Providers without a type index return `None`
+    /// and the query scans every event instead.
+    fn instantiate_types_data_source<'a>(
+        &'a self,
+        _session: &Arc<Session>,
+        _types: Vec<&str>,
+        _inferred_type: Type,
+    ) -> Option<QueryProcessor<'a>> {
+        None
+    }
+
+    /// Number of rows `source` yields, when the provider can tell without scanning it. The planner
+    /// uses it to skip work that doesn't pay off, like bounding the sort of a `TOP n` query over
+    /// fewer than `n` rows.
+    fn estimated_cardinality(&self, _source: DataSource<'_>) -> Option<usize> {
+        None
+    }
+}
+
+/// A source a [`DataProvider`] instantiates, as named in a query.
+pub enum DataSource<'a> {
+    Named(&'a str),
+    Subject(&'a str),
+    /// The `events` source narrowed to a few event types.
+    Types(&'a [&'a str]),
 }
 
 pub fn query_plan<'a, P>(
-    session: &'a Session,
+    session: &Arc<Session>,
+    provider: &'a P,
+    options: QueryOptions,
+    query: Query<Typed>,
+) -> QueryProcessor<'a>
+where
+    P: DataProvider,
+{
+    let budget = RowBudget::new(options.row_budget);
+    // subqueries get their own interpreter, they all have to agree on `NOW()`
+    let options = QueryOptions {
+        now: Some(options.now.unwrap_or_else(Utc::now)),
+        ..options
+    };
+
+    plan(
+        session,
+        provider,
+        options,
+        &budget,
+        &CancellationHandle::default(),
+        query,
+    )
+}
+
+fn plan<'a, P>(
+    session: &Arc<Session>,
     provider: &'a P,
+    options: QueryOptions,
+    budget: &RowBudget,
+    cancellation: &CancellationHandle,
     query: Query<Typed>,
 ) -> QueryProcessor<'a>
 where
     P: DataProvider,
 {
-    let mut srcs = Sources::default();
+    let mut srcs = Sources::new(budget.clone(), cancellation.clone());
     for query_src in &query.sources {
         match &query_src.kind {
-            eventql_parser::SourceKind::Name(name) => {
-                let proc = if let Some(tpe) = query.meta.scope.get(query_src.binding.name) {
-                    let name = session.arena().get_str(*name);
-
-                    provider
-                        .instantiate_named_data_source(name, tpe)
-                        .unwrap_or(QueryProcessor::empty())
-                } else {
-                    QueryProcessor::empty()
-                };
+            SourceKind::Name(name) => {
+                let (proc, estimate) =
+                    if let Some(tpe) = query.meta.scope.get(query_src.binding.name) {
+                        let name = session.arena().get_str(*name);
+                        let types = name
+                            .eq_ignore_ascii_case("events")
+                            .then(|| {
+                                query.predicate.and_then(|predicate| {
+                                    event_types(session, query_src.binding.name, predicate)
+                                })
+                            })
+                            .flatten();
 
-                srcs.insert(query_src.binding.name, proc);
+                        let estimate = match &types {
+                            Some(types) => provider.estimated_cardinality(DataSource::Types(types)),
+                            None => provider.estimated_cardinality(DataSource::Named(name)),
+                        };
+
+                        let proc = types
+                            .and_then(|types| {
+                                provider.instantiate_types_data_source(session, types, tpe)
+                            })
+                            .or_else(|| provider.instantiate_named_data_source(session, name, tpe))
+                            .unwrap_or(QueryProcessor::empty());
+
+                        (proc, estimate)
+                    } else {
+                        (QueryProcessor::empty(), None)
+                    };
+
+                srcs.insert(query_src.binding.name, proc, estimate);
             }
 
-            eventql_parser::SourceKind::Subject(sub) => {
-                let proc = if let Some(tpe) = query.meta.scope.get(query_src.binding.name) {
-                    let sub = session.arena().get_str(*sub);
-                    provider
-                        .instantiate_subject_data_source(sub, tpe)
-                        .unwrap_or(QueryProcessor::empty())
-                } else {
-                    QueryProcessor::empty()
-                };
+            SourceKind::Subject(sub) => {
+                let (proc, estimate) =
+                    if let Some(tpe) = query.meta.scope.get(query_src.binding.name) {
+                        let sub = session.arena().get_str(*sub);
+                        let proc = provider
+                            .instantiate_subject_data_source(session, sub, tpe)
+                            .unwrap_or(QueryProcessor::empty());
+
+                        (
+                            proc,
+                            provider.estimated_cardinality(DataSource::Subject(sub)),
+                        )
+                    } else {
+                        (QueryProcessor::empty(), None)
+                    };
 
-                srcs.insert(query_src.binding.name, proc);
+                srcs.insert(query_src.binding.name, proc, estimate);
             }
 
-            eventql_parser::SourceKind::Subquery(sub_query) => {
+            SourceKind::Subquery(sub_query) => {
                 let name = query_src.binding.name;
                 // TODO - get rid of that unnecessary clone
-                let proc = query_plan(session, provider, sub_query.as_ref().clone());
+                let proc = plan(
+                    session,
+                    provider,
+                    options,
+                    budget,
+                    cancellation,
+                    sub_query.as_ref().clone(),
+                );
 
-                srcs.insert(name, proc);
+                srcs.insert(name, proc, None);
             }
         }
     }
 
     if query.meta.aggregate {
-        match AggQuery::new(srcs, session, query) {
+        match AggQuery::new(srcs, session.clone(), options, query) {
             Ok(agg_query) => QueryProcessor::Aggregate(agg_query),
             Err(e) => QueryProcessor::Errored(Some(e)),
         }
     } else {
-        QueryProcessor::Regular(EventQuery::new(srcs, session, query))
+        QueryProcessor::Regular(EventQuery::new(srcs, session.clone(), options, query))
+    }
+}
+
+/// Describes how [`query_plan`] would run `query`, subqueries included, without running it.
+pub fn explain(session: &Session, query: &Query<Typed>) -> QueryPlan {
+    let arena = session.arena();
+    let sources = query
+        .sources
+        .iter()
+        .map(|query_src| {
+            let binding = arena.get_str(query_src.binding.name).to_owned();
+
+            match &query_src.kind {
+                SourceKind::Name(name) => {
+                    let name = arena.get_str(*name);
+                    let event_types = name
+                        .eq_ignore_ascii_case("events")
+                        .then(|| {
+                            query.predicate.and_then(|predicate| {
+                                event_types(session, query_src.binding.name, predicate)
+                            })
+                        })
+                        .flatten()
+                        .map(|types| types.into_iter().map(str::to_owned).collect());
+
+                    SourcePlan::Named {
+                        binding,
+                        name: name.to_owned(),
+                        event_types,
+                    }
+                }
+
+                SourceKind::Subject(sub) => SourcePlan::Subject {
+                    binding,
+                    subject: arena.get_str(*sub).to_owned(),
+                },
+
+                SourceKind::Subquery(sub_query) => SourcePlan::Subquery {
+                    binding,
+                    plan: Box::new(explain(session, sub_query)),
+                },
+            }
+        })
+        .collect();
+
+    QueryPlan {
+        sources,
+        aggregate: query.meta.aggregate,
+        grouped: query.group_by.is_some(),
+        having: query
+            .group_by
+            .as_ref()
+            .is_some_and(|group_by| group_by.predicate.is_some()),
+        distinct: query.distinct,
+        order: query.order_by.map(|order_by| {
+            if is_rand_call(session, order_by.expr) {
+                PlanOrder::Shuffled
+            } else if matches!(order_by.order, Order::Asc) {
+                PlanOrder::Asc
+            } else {
+                PlanOrder::Desc
+            }
+        }),
+        limit: query.limit.map(|limit| match limit {
+            Limit::Skip(n) => PlanLimit::Skip(n),
+            Limit::Top(n) => PlanLimit::Top(n),
+        }),
+    }
+}
+
+/// Event types `predicate` restricts `binding` to, when it only lets through events whose `type`
+/// equals one of a few string literals, like `e.type == "a" OR e.type == "b"`. The predicate still
+/// gets evaluated on every row, so this only narrows what needs to be scanned.
+fn event_types(session: &Session, binding: StrRef, predicate: ExprRef) -> Option<Vec<&str>> {
+    let arena = session.arena();
+    let Value::Binary(binary) = arena.get_expr(predicate).value else {
+        return None;
+    };
+
+    match binary.operator {
+        Operator::Eq => {
+            let is_type_access = |expr| {
+                let Value::Access(access) = arena.get_expr(expr).value else {
+                    return false;
+                };
+
+                arena.get_str(access.field) == "type"
+                    && matches!(arena.get_expr(access.target).value, Value::Id(id) if id == binding)
+            };
+
+            let literal = |expr| match arena.get_expr(expr).value {
+                Value::String(s) => Some(vec![arena.get_str(s)]),
+                _ => None,
+            };
+
+            if is_type_access(binary.lhs) {
+                literal(binary.rhs)
+            } else if is_type_access(binary.rhs) {
+                literal(binary.lhs)
+            } else {
+                None
+            }
+        }
+
+        // either side narrowing the scan is enough
+        Operator::And => event_types(session, binding, binary.lhs)
+            .or_else(|| event_types(session, binding, binary.rhs)),
+
+        // both sides have to narrow the scan
+        Operator::Or => {
+            let mut types = event_types(session, binding, binary.lhs)?;
+            types.extend(event_types(session, binding, binary.rhs)?);
+
+            Some(types)
+        }
+
+        _ => None,
     }
 }
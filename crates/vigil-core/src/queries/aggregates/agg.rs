@@ -1,16 +1,96 @@
-use crate::values::QueryValue;
+use std::collections::BTreeMap;
+
+use crate::{
+    eval::{EvalError, EvalResult},
+    values::{QueryValue, Record},
+};
 
 #[derive(Clone)]
 pub enum Agg {
-    Avg { count: u64, acc: f64 },
-    Count { value: u64 },
-    Unique { value: Option<QueryValue> },
-    Sum { acc: f64 },
-    Min { value: Option<f64> },
-    Max { value: Option<f64> },
-    Median { values: Vec<f64> },
-    Stddev { count: u64, sum: f64, sum_sq: f64 },
-    Variance { count: u64, sum: f64, sum_sq: f64 },
+    Avg {
+        count: u64,
+        acc: f64,
+    },
+    Count {
+        value: u64,
+    },
+    CountIf {
+        value: u64,
+    },
+    Unique {
+        value: Option<QueryValue>,
+    },
+    UniqueStrict {
+        value: Option<QueryValue>,
+        conflict: bool,
+    },
+    Sum {
+        acc: f64,
+    },
+    Min {
+        value: Option<f64>,
+    },
+    Max {
+        value: Option<f64>,
+    },
+    Median {
+        values: Vec<f64>,
+    },
+    Stddev {
+        count: u64,
+        mean: f64,
+        m2: f64,
+        sample: bool,
+    },
+    Variance {
+        count: u64,
+        mean: f64,
+        m2: f64,
+        sample: bool,
+    },
+    JsonObject {
+        value: Record,
+    },
+    Array {
+        items: Vec<QueryValue>,
+    },
+    Histogram {
+        width: f64,
+        buckets: BTreeMap<i64, u64>,
+    },
+    Covar(Moments),
+    Corr(Moments),
+}
+
+/// Running sums of two variables folded together, enough to get their covariance and correlation.
+#[derive(Clone, Copy, Default)]
+pub struct Moments {
+    n: u64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xy: f64,
+    sum_x2: f64,
+    sum_y2: f64,
+}
+
+impl Moments {
+    fn fold(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xy += x * y;
+        self.sum_x2 += x * x;
+        self.sum_y2 += y * y;
+    }
+
+    fn merge(&mut self, other: &Moments) {
+        self.n += other.n;
+        self.sum_x += other.sum_x;
+        self.sum_y += other.sum_y;
+        self.sum_xy += other.sum_xy;
+        self.sum_x2 += other.sum_x2;
+        self.sum_y2 += other.sum_y2;
+    }
 }
 
 impl Agg {
@@ -21,14 +101,30 @@ impl Agg {
         }
     }
 
+    /// `COUNT()` counts rows and `COUNT(expr)` counts non-null values. A boolean argument still
+    /// only counts `true` values for backward compatibility, but new queries should spell that as
+    /// `COUNT_IF(predicate)`.
     pub fn count() -> Self {
         Self::Count { value: 0 }
     }
 
+    pub fn count_if() -> Self {
+        Self::CountIf { value: 0 }
+    }
+
     pub fn unique() -> Self {
         Self::Unique { value: None }
     }
 
+    /// `UNIQUE_STRICT(expr)` is `UNIQUE(expr)` expecting a single distinct value: folding a second
+    /// one fails the query once the aggregate completes, instead of keeping the first value.
+    pub fn unique_strict() -> Self {
+        Self::UniqueStrict {
+            value: None,
+            conflict: false,
+        }
+    }
+
     pub fn sum() -> Self {
         Self::Sum { acc: 0f64 }
     }
@@ -45,22 +141,89 @@ impl Agg {
         Self::Median { values: Vec::new() }
     }
 
+    /// Stddev and variance use Welford's online algorithm: `m2` is the sum of squared distances
+    /// to the running `mean`, which never goes negative unlike the naive sum of squares.
+    /// `STDDEV` and `VARIANCE` are the population flavors, dividing `m2` by the count.
     pub fn stddev() -> Self {
+        Self::stddev_pop()
+    }
+
+    pub fn variance() -> Self {
+        Self::var_pop()
+    }
+
+    pub fn stddev_pop() -> Self {
         Self::Stddev {
             count: 0,
-            sum: 0f64,
-            sum_sq: 0f64,
+            mean: 0f64,
+            m2: 0f64,
+            sample: false,
         }
     }
 
-    pub fn variance() -> Self {
+    pub fn var_pop() -> Self {
+        Self::Variance {
+            count: 0,
+            mean: 0f64,
+            m2: 0f64,
+            sample: false,
+        }
+    }
+
+    /// Sample flavors divide `m2` by the count minus one, and need at least two values.
+    pub fn stddev_samp() -> Self {
+        Self::Stddev {
+            count: 0,
+            mean: 0f64,
+            m2: 0f64,
+            sample: true,
+        }
+    }
+
+    pub fn var_samp() -> Self {
         Self::Variance {
             count: 0,
-            sum: 0f64,
-            sum_sq: 0f64,
+            mean: 0f64,
+            m2: 0f64,
+            sample: true,
         }
     }
 
+    /// `JSON_OBJECT_AGG(key, value)` builds a record out of `(key, value)` pairs. Keys are
+    /// stringified and the last value wins on duplicate keys. `Null`, array or record keys are
+    /// skipped.
+    pub fn json_object_agg() -> Self {
+        Self::JsonObject {
+            value: Record::new(),
+        }
+    }
+
+    /// `ARRAY_AGG(expr)` collects every folded value, `Null` included, in folding order.
+    pub fn array_agg() -> Self {
+        Self::Array { items: Vec::new() }
+    }
+
+    /// `HISTOGRAM(width, expr)` counts numeric values per bucket of `width`, buckets starting at
+    /// multiples of it. Completes into a record of bucket lower bound to count, in ascending bucket
+    /// order. Non-numeric values are skipped.
+    pub fn histogram(width: f64) -> Self {
+        Self::Histogram {
+            width,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// `COVAR(x, y)` is the population covariance of `x` and `y`, `CORR(x, y)` their Pearson
+    /// correlation. Rows where either isn't a number are skipped, and fewer than two rows give
+    /// `Null`.
+    pub fn covar() -> Self {
+        Self::Covar(Moments::default())
+    }
+
+    pub fn corr() -> Self {
+        Self::Corr(Moments::default())
+    }
+
     pub fn fold(&mut self, params: &[QueryValue]) {
         match self {
             Agg::Avg { count, acc } => {
@@ -76,18 +239,17 @@ impl Agg {
                 *acc = f64::NAN;
             }
 
-            Agg::Count { value } => {
-                if !params.is_empty() {
-                    if let QueryValue::Bool(is_true) = params[0]
-                        && is_true
-                    {
-                        *value += 1;
-                    }
+            Agg::Count { value } => match params.first() {
+                None => *value += 1,
+                Some(QueryValue::Bool(is_true)) => *value += *is_true as u64,
+                Some(QueryValue::Null) => {}
+                Some(_) => *value += 1,
+            },
 
-                    return;
+            Agg::CountIf { value } => {
+                if let Some(QueryValue::Bool(true)) = params.first() {
+                    *value += 1;
                 }
-
-                *value += 1;
             }
 
             Agg::Unique { value } => {
@@ -98,6 +260,17 @@ impl Agg {
                 *value = Some(params[0].clone());
             }
 
+            Agg::UniqueStrict { value, conflict } => {
+                let Some(param) = params.first() else {
+                    return;
+                };
+
+                match value {
+                    None => *value = Some(param.clone()),
+                    Some(current) => *conflict |= current != param,
+                }
+            }
+
             Agg::Sum { acc } => {
                 if !params.is_empty()
                     && let QueryValue::Number(n) = params[0]
@@ -153,28 +326,192 @@ impl Agg {
                 values.push(f64::NAN);
             }
 
-            Agg::Stddev { count, sum, sum_sq } | Agg::Variance { count, sum, sum_sq } => {
+            Agg::Stddev {
+                count, mean, m2, ..
+            }
+            | Agg::Variance {
+                count, mean, m2, ..
+            } => {
                 if !params.is_empty()
                     && let QueryValue::Number(n) = params[0]
                 {
                     let n = *n;
                     *count += 1;
-                    *sum += n;
-                    *sum_sq += n * n;
+                    let delta = n - *mean;
+                    *mean += delta / *count as f64;
+                    *m2 += delta * (n - *mean);
 
                     return;
                 }
 
-                *sum = f64::NAN;
+                *mean = f64::NAN;
+            }
+
+            Agg::JsonObject { value } => {
+                if let [key, field, ..] = params
+                    && let Some(key) = stringify_key(key)
+                {
+                    value.insert(key, field.clone());
+                }
+            }
+
+            Agg::Array { items } => {
+                if let Some(value) = params.first() {
+                    items.push(value.clone());
+                }
+            }
+
+            Agg::Histogram { width, buckets } => {
+                // the first parameter is the bucket width
+                if let Some(QueryValue::Number(n)) = params.get(1)
+                    && n.is_finite()
+                {
+                    *buckets.entry((**n / *width).floor() as i64).or_default() += 1;
+                }
+            }
+
+            Agg::Covar(moments) | Agg::Corr(moments) => {
+                if let [QueryValue::Number(x), QueryValue::Number(y), ..] = params {
+                    moments.fold(**x, **y);
+                }
             }
         }
     }
 
-    pub fn complete(&self) -> QueryValue {
-        match self {
+    /// Combines the partial state `other`, folded over the rows following the ones `self` saw,
+    /// into `self`. Fails when both states don't come from the same aggregate function.
+    pub fn merge(&mut self, other: &Agg) -> EvalResult<()> {
+        match (self, other) {
+            (Agg::Avg { count, acc }, Agg::Avg { count: c, acc: a }) => {
+                *count += c;
+                *acc += a;
+            }
+
+            (Agg::Count { value }, Agg::Count { value: v })
+            | (Agg::CountIf { value }, Agg::CountIf { value: v }) => *value += v,
+
+            (Agg::Unique { value }, Agg::Unique { value: v }) => {
+                if value.is_none() {
+                    value.clone_from(v);
+                }
+            }
+
+            (
+                Agg::UniqueStrict { value, conflict },
+                Agg::UniqueStrict {
+                    value: v,
+                    conflict: c,
+                },
+            ) => {
+                *conflict |= *c || matches!((&*value, v), (Some(a), Some(b)) if a != b);
+
+                if value.is_none() {
+                    value.clone_from(v);
+                }
+            }
+
+            (Agg::Sum { acc }, Agg::Sum { acc: a }) => *acc += a,
+
+            (Agg::Min { value }, Agg::Min { value: v }) => {
+                *value = match (*value, *v) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+
+            (Agg::Max { value }, Agg::Max { value: v }) => {
+                *value = match (*value, *v) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+
+            (Agg::Median { values }, Agg::Median { values: v }) => values.extend_from_slice(v),
+
+            // Chan et al. pairwise update of Welford's running mean and squared distances
+            (
+                Agg::Stddev {
+                    count,
+                    mean,
+                    m2,
+                    sample,
+                },
+                Agg::Stddev {
+                    count: c,
+                    mean: m,
+                    m2: s,
+                    sample: p,
+                },
+            )
+            | (
+                Agg::Variance {
+                    count,
+                    mean,
+                    m2,
+                    sample,
+                },
+                Agg::Variance {
+                    count: c,
+                    mean: m,
+                    m2: s,
+                    sample: p,
+                },
+            ) if sample == p => {
+                if mean.is_nan() || m.is_nan() {
+                    *mean = f64::NAN;
+                    return Ok(());
+                }
+
+                let total = *count + c;
+                if total == 0 {
+                    return Ok(());
+                }
+
+                let delta = m - *mean;
+                *mean += delta * *c as f64 / total as f64;
+                *m2 += s + delta * delta * (*count as f64) * (*c as f64) / total as f64;
+                *count = total;
+            }
+
+            (Agg::JsonObject { value }, Agg::JsonObject { value: v }) => {
+                for (key, field) in v.iter() {
+                    value.insert(key.clone(), field.clone());
+                }
+            }
+
+            (Agg::Array { items }, Agg::Array { items: v }) => items.extend_from_slice(v),
+
+            (
+                Agg::Histogram { width, buckets },
+                Agg::Histogram {
+                    width: w,
+                    buckets: b,
+                },
+            ) if width == w => {
+                for (bucket, count) in b {
+                    *buckets.entry(*bucket).or_default() += count;
+                }
+            }
+
+            (Agg::Covar(moments), Agg::Covar(m)) | (Agg::Corr(moments), Agg::Corr(m)) => {
+                moments.merge(m)
+            }
+
+            _ => {
+                return Err(EvalError::Runtime(
+                    "cannot merge states of different aggregate functions".into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn complete(&self) -> EvalResult<QueryValue> {
+        let value = match self {
             Agg::Avg { count, acc } => {
                 if acc.is_nan() {
-                    return QueryValue::Number(f64::NAN.into());
+                    return Ok(QueryValue::Number(f64::NAN.into()));
                 }
 
                 if *count == 0 {
@@ -184,9 +521,19 @@ impl Agg {
                 }
             }
 
-            Agg::Count { value } => QueryValue::Number((*value as f64).into()),
+            Agg::Count { value } | Agg::CountIf { value } => {
+                QueryValue::Number((*value as f64).into())
+            }
             Agg::Unique { value } => value.clone().unwrap_or(QueryValue::Null),
 
+            Agg::UniqueStrict { conflict: true, .. } => {
+                return Err(EvalError::Runtime(
+                    "UNIQUE_STRICT expects a single distinct value but got several".into(),
+                ));
+            }
+
+            Agg::UniqueStrict { value, .. } => value.clone().unwrap_or(QueryValue::Null),
+
             Agg::Sum { acc } => QueryValue::Number((*acc).into()),
 
             Agg::Min { value } => value
@@ -199,7 +546,7 @@ impl Agg {
 
             Agg::Median { values } => {
                 if values.is_empty() {
-                    return QueryValue::Null;
+                    return Ok(QueryValue::Null);
                 }
 
                 let mut sorted = values.clone();
@@ -214,35 +561,119 @@ impl Agg {
                 QueryValue::Number(median.into())
             }
 
-            Agg::Stddev { count, sum, sum_sq } => {
-                if sum.is_nan() {
-                    return QueryValue::Number(f64::NAN.into());
+            Agg::Stddev {
+                count,
+                mean,
+                m2,
+                sample,
+            } => {
+                if mean.is_nan() {
+                    return Ok(QueryValue::Number(f64::NAN.into()));
                 }
 
-                if *count == 0 {
-                    return QueryValue::Null;
-                }
+                let Some(divisor) = count.checked_sub(*sample as u64).filter(|n| *n > 0) else {
+                    return Ok(QueryValue::Null);
+                };
 
-                let mean = sum / *count as f64;
-                let variance = sum_sq / *count as f64 - mean * mean;
+                let variance = m2 / divisor as f64;
 
                 QueryValue::Number(variance.sqrt().into())
             }
 
-            Agg::Variance { count, sum, sum_sq } => {
-                if sum.is_nan() {
-                    return QueryValue::Number(f64::NAN.into());
+            Agg::Variance {
+                count,
+                mean,
+                m2,
+                sample,
+            } => {
+                if mean.is_nan() {
+                    return Ok(QueryValue::Number(f64::NAN.into()));
                 }
 
-                if *count == 0 {
-                    return QueryValue::Null;
-                }
+                let Some(divisor) = count.checked_sub(*sample as u64).filter(|n| *n > 0) else {
+                    return Ok(QueryValue::Null);
+                };
 
-                let mean = sum / *count as f64;
-                let variance = sum_sq / *count as f64 - mean * mean;
+                let variance = m2 / divisor as f64;
 
                 QueryValue::Number(variance.into())
             }
-        }
+
+            Agg::JsonObject { value } => QueryValue::Record(value.clone()),
+
+            Agg::Array { items } => QueryValue::Array(items.clone()),
+
+            Agg::Histogram { width, buckets } => QueryValue::Record(
+                buckets
+                    .iter()
+                    .map(|(bucket, count)| {
+                        (
+                            bucket_bound(*bucket, *width),
+                            QueryValue::Number((*count as f64).into()),
+                        )
+                    })
+                    .collect(),
+            ),
+
+            Agg::Covar(m) => {
+                if m.n < 2 {
+                    return Ok(QueryValue::Null);
+                }
+
+                let n = m.n as f64;
+                QueryValue::Number(((m.sum_xy - m.sum_x * m.sum_y / n) / n).into())
+            }
+
+            Agg::Corr(m) => {
+                if m.n < 2 {
+                    return Ok(QueryValue::Null);
+                }
+
+                let n = m.n as f64;
+                let spread = ((n * m.sum_x2 - m.sum_x * m.sum_x)
+                    * (n * m.sum_y2 - m.sum_y * m.sum_y))
+                    .sqrt();
+
+                // a constant variable has no correlation to speak of
+                if spread == 0.0 {
+                    return Ok(QueryValue::Null);
+                }
+
+                QueryValue::Number(((n * m.sum_xy - m.sum_x * m.sum_y) / spread).into())
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// Renders a scalar value as a record key.
+fn stringify_key(key: &QueryValue) -> Option<String> {
+    match key {
+        QueryValue::String(s) => Some(s.clone()),
+        QueryValue::Number(n) => Some(n.to_string()),
+        QueryValue::Bool(b) => Some(b.to_string()),
+        QueryValue::DateTime(t) => Some(t.to_string()),
+        QueryValue::Date(d) => Some(d.to_string()),
+        QueryValue::Time(t) => Some(t.to_string()),
+        QueryValue::Null | QueryValue::Record(_) | QueryValue::Array(_) => None,
     }
 }
+
+/// Lower bound of a histogram bucket rendered as a record key, the way numbers are serialized.
+/// Rounded to the decimals of `width`, so the fourth bucket of width 0.1 is `0.3` rather than
+/// `0.30000000000000004`.
+fn bucket_bound(bucket: i64, width: f64) -> String {
+    let decimals = width
+        .to_string()
+        .split_once('.')
+        .map_or(0, |(_, fract)| fract.len());
+    let scale = 10f64.powi(decimals as i32);
+    let lower = if scale.is_finite() {
+        bucket as f64 * (width * scale).round() / scale
+    } else {
+        bucket as f64 * width
+    };
+
+    serde_json::to_string(&QueryValue::Number(lower.into()).plain_json()).unwrap_or_default()
+}
@@ -1,19 +1,22 @@
-mod agg;
+pub(crate) mod agg;
 
 use crate::queries::aggregates::agg::Agg;
+use crate::queries::events::order_keys;
 use crate::queries::orderer::QueryOrderer;
 use crate::{
     eval::{EvalError, EvalResult, Interpreter},
-    queries::Sources,
-    values::QueryValue,
+    queries::{CancellationHandle, GapFill, NullsPlacement, QueryOptions, QueryStats, Sources},
+    values::{QueryValue, Record},
 };
+use chrono::{TimeDelta, Utc};
 use eventql_parser::{
     App, ExprRef, Limit, Query, Session, Value,
     prelude::{Type, Typed},
 };
+use rayon::prelude::*;
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::{mem, vec};
+use std::collections::{HashMap, HashSet};
+use std::{mem, sync::Arc, vec};
 
 fn instantiate_aggregate(session: &Session, app: &App) -> EvalResult<Agg> {
     if let Some(Type::App {
@@ -23,10 +26,14 @@ fn instantiate_aggregate(session: &Session, app: &App) -> EvalResult<Agg> {
         let fun_name = session.arena().get_str(app.func);
         return if fun_name.eq_ignore_ascii_case("count") {
             Ok(Agg::count())
+        } else if fun_name.eq_ignore_ascii_case("count_if") {
+            Ok(Agg::count_if())
         } else if fun_name.eq_ignore_ascii_case("avg") {
             Ok(Agg::avg())
         } else if fun_name.eq_ignore_ascii_case("unique") {
             Ok(Agg::unique())
+        } else if fun_name.eq_ignore_ascii_case("unique_strict") {
+            Ok(Agg::unique_strict())
         } else if fun_name.eq_ignore_ascii_case("sum") {
             Ok(Agg::sum())
         } else if fun_name.eq_ignore_ascii_case("min") {
@@ -37,8 +44,41 @@ fn instantiate_aggregate(session: &Session, app: &App) -> EvalResult<Agg> {
             Ok(Agg::median())
         } else if fun_name.eq_ignore_ascii_case("stddev") {
             Ok(Agg::stddev())
+        } else if fun_name.eq_ignore_ascii_case("stddev_pop") {
+            Ok(Agg::stddev_pop())
+        } else if fun_name.eq_ignore_ascii_case("stddev_samp") {
+            Ok(Agg::stddev_samp())
         } else if fun_name.eq_ignore_ascii_case("variance") {
             Ok(Agg::variance())
+        } else if fun_name.eq_ignore_ascii_case("var_pop") {
+            Ok(Agg::var_pop())
+        } else if fun_name.eq_ignore_ascii_case("var_samp") {
+            Ok(Agg::var_samp())
+        } else if fun_name.eq_ignore_ascii_case("json_object_agg") {
+            Ok(Agg::json_object_agg())
+        } else if fun_name.eq_ignore_ascii_case("array_agg") {
+            Ok(Agg::array_agg())
+        } else if fun_name.eq_ignore_ascii_case("covar") {
+            Ok(Agg::covar())
+        } else if fun_name.eq_ignore_ascii_case("corr") {
+            Ok(Agg::corr())
+        } else if fun_name.eq_ignore_ascii_case("histogram") {
+            // the bucket width has to be known before folding anything
+            let width = session
+                .arena()
+                .get_vec(app.args)
+                .first()
+                .map(|arg| session.arena().get_expr(*arg).value);
+
+            match width {
+                Some(Value::Number(width)) if width.is_finite() && *width > 0.0 => {
+                    Ok(Agg::histogram(*width))
+                }
+
+                _ => Err(EvalError::Runtime(
+                    "HISTOGRAM expects a positive number literal as bucket width".into(),
+                )),
+            }
         } else {
             Err(EvalError::Runtime(
                 format!("unknown aggregate function: {fun_name}").into(),
@@ -57,12 +97,14 @@ enum AggLayout {
         base: HashMap<App, Agg>,
         value: Value,
         having: Option<ExprRef>,
+        normalize_keys: bool,
+        max_groups: Option<usize>,
         aggs: HashMap<QueryValue, HashMap<App, Agg>>,
     },
 }
 
 impl AggLayout {
-    fn load(session: &Session, query: &Query<Typed>) -> EvalResult<Self> {
+    fn load(session: &Session, options: QueryOptions, query: &Query<Typed>) -> EvalResult<Self> {
         let mut aggs = HashMap::new();
 
         Self::load_expr(&mut aggs, session, query.projection)?;
@@ -80,6 +122,8 @@ impl AggLayout {
                 base: aggs,
                 value: session.arena().get_expr(group_by.expr).value,
                 having: group_by.predicate,
+                normalize_keys: options.normalize_group_keys,
+                max_groups: options.max_groups,
                 aggs: Default::default(),
             })
         } else {
@@ -122,27 +166,199 @@ impl AggLayout {
     }
 }
 
+/// Rows evaluated ahead of a parallel fold. Bounds the memory taken by rows waiting to be folded.
+const PARALLEL_FOLD_ROWS: usize = if cfg!(test) { 8 } else { 16 * 1024 };
+
+/// Aggregate arguments of a row, evaluated ahead of a parallel fold.
+struct FoldRow {
+    key: QueryValue,
+    args: Vec<(App, Vec<QueryValue>)>,
+}
+
 #[derive(Default)]
 struct AggEvaluator {
     buffer: Vec<QueryValue>,
+    nulls: NullsPlacement,
+    strict: bool,
+    gap_fill: Option<GapFill>,
+    parallel: bool,
+    rows: Vec<FoldRow>,
 }
 
 impl AggEvaluator {
     fn fold(&mut self, interpreter: &Interpreter, kind: &mut AggLayout) -> EvalResult<()> {
+        if self.parallel {
+            return self.collect_row(interpreter, kind);
+        }
+
         match kind {
             AggLayout::Regular(aggs) => self.fold_aggs(interpreter, aggs),
 
             AggLayout::Grouped {
-                base, value, aggs, ..
+                base,
+                value,
+                normalize_keys,
+                max_groups,
+                aggs,
+                ..
             } => {
-                let key = interpreter.eval(*value)?;
-                let aggs = aggs.entry(key).or_insert_with(|| base.clone());
+                let mut key = interpreter.eval(*value)?;
+
+                if *normalize_keys && let QueryValue::String(s) = &key {
+                    key = QueryValue::String(s.trim().to_lowercase());
+                }
+
+                let count = aggs.len();
+                let aggs = match aggs.entry(key) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        if let Some(max) = *max_groups
+                            && count >= max
+                        {
+                            return Err(groups_exceeded(max));
+                        }
+
+                        entry.insert(base.clone())
+                    }
+                };
 
                 self.fold_aggs(interpreter, aggs)
             }
         }
     }
 
+    fn collect_row(&mut self, interpreter: &Interpreter, kind: &mut AggLayout) -> EvalResult<()> {
+        let (key, aggs) = match kind {
+            AggLayout::Regular(aggs) => (QueryValue::Null, aggs),
+            AggLayout::Grouped {
+                base,
+                value,
+                normalize_keys,
+                ..
+            } => {
+                let mut key = interpreter.eval(*value)?;
+
+                if *normalize_keys && let QueryValue::String(s) = &key {
+                    key = QueryValue::String(s.trim().to_lowercase());
+                }
+
+                (key, base)
+            }
+        };
+
+        let mut args = Vec::with_capacity(aggs.len());
+        for app in aggs.keys() {
+            let mut values = Vec::new();
+
+            for arg in interpreter.session.arena().get_vec(app.args) {
+                if self.strict {
+                    ensure_field_exists(interpreter, *arg)?;
+                }
+
+                values.push(interpreter.eval_expr(*arg)?);
+            }
+
+            args.push((*app, values));
+        }
+
+        self.rows.push(FoldRow { key, args });
+
+        if self.rows.len() >= PARALLEL_FOLD_ROWS {
+            self.fold_parallel(kind)?;
+        }
+
+        Ok(())
+    }
+
+    /// Folds the collected rows, one partition per thread, and merges the partial states back in
+    /// partition order so order-sensitive aggregates like `UNIQUE` or `ARRAY_AGG` see the rows
+    /// the way a serial fold would. Called every [`PARALLEL_FOLD_ROWS`] rows, and once more when
+    /// the sources are exhausted.
+    fn fold_parallel(&mut self, kind: &mut AggLayout) -> EvalResult<()> {
+        let rows = mem::take(&mut self.rows);
+        let size = rows.len().div_ceil(rayon::current_num_threads()).max(1);
+
+        match kind {
+            AggLayout::Regular(aggs) => {
+                let partials = rows
+                    .par_chunks(size)
+                    .map(|rows| {
+                        let mut partial = aggs.clone();
+                        for row in rows {
+                            fold_row(&mut partial, row);
+                        }
+
+                        partial
+                    })
+                    .collect::<Vec<_>>();
+
+                for partial in partials {
+                    merge_aggs(aggs, partial)?;
+                }
+            }
+
+            AggLayout::Grouped {
+                base,
+                max_groups,
+                aggs,
+                ..
+            } => {
+                let partials = rows
+                    .par_chunks(size)
+                    .map(|rows| {
+                        let mut partial = HashMap::<QueryValue, HashMap<App, Agg>>::new();
+                        // groups of this partition the merge is going to add, enough to fail
+                        // early without waiting for the other partitions
+                        let mut added = 0;
+
+                        for row in rows {
+                            let group = match partial.entry(row.key.clone()) {
+                                Entry::Occupied(entry) => entry.into_mut(),
+                                Entry::Vacant(entry) => {
+                                    if !aggs.contains_key(entry.key()) {
+                                        added += 1;
+                                    }
+
+                                    if let Some(max) = *max_groups
+                                        && aggs.len() + added > max
+                                    {
+                                        return Err(groups_exceeded(max));
+                                    }
+
+                                    entry.insert(base.clone())
+                                }
+                            };
+
+                            fold_row(group, row);
+                        }
+
+                        Ok(partial)
+                    })
+                    .collect::<EvalResult<Vec<_>>>()?;
+
+                for partial in partials {
+                    for (key, group) in partial {
+                        let count = aggs.len();
+                        match aggs.entry(key) {
+                            Entry::Occupied(mut entry) => merge_aggs(entry.get_mut(), group)?,
+                            Entry::Vacant(entry) => {
+                                if let Some(max) = *max_groups
+                                    && count >= max
+                                {
+                                    return Err(groups_exceeded(max));
+                                }
+
+                                entry.insert(group);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn fold_aggs(
         &mut self,
         interpreter: &Interpreter,
@@ -150,6 +366,10 @@ impl AggEvaluator {
     ) -> EvalResult<()> {
         for (app, agg) in aggs.iter_mut() {
             for arg in interpreter.session.arena().get_vec(app.args) {
+                if self.strict {
+                    ensure_field_exists(interpreter, *arg)?;
+                }
+
                 self.buffer.push(interpreter.eval_expr(*arg)?);
             }
 
@@ -166,39 +386,92 @@ impl AggEvaluator {
         kind: &mut AggLayout,
         query: &Query<Typed>,
     ) -> EvalResult<()> {
+        if self.parallel {
+            self.fold_parallel(kind)?;
+        }
+
         match kind {
             AggLayout::Regular(aggs) => {
-                let value = self.complete_aggs(interpreter, aggs, query.projection)?;
+                let value = self.complete_aggs(interpreter, aggs, None, query.projection)?;
                 self.buffer.push(value);
             }
 
-            AggLayout::Grouped { aggs, having, .. } => {
+            AggLayout::Grouped {
+                base,
+                aggs,
+                having,
+                max_groups,
+                ..
+            } => {
                 let having = having.as_ref().copied();
 
-                if let Some(order_by) = query.order_by {
-                    let mut orderer = QueryOrderer::new(order_by.order);
+                if let Some(fill) = self.gap_fill
+                    && let Some(group_by) = &query.group_by
+                {
+                    let seeds = base
+                        .keys()
+                        .filter(|app| {
+                            matches!(
+                                interpreter.session.arena().get_vec(app.args),
+                                [arg] if same_expr(&interpreter.session, *arg, group_by.expr)
+                            )
+                        })
+                        .copied()
+                        .collect::<HashSet<_>>();
+
+                    fill_gaps(aggs, base, &seeds, *max_groups, fill)?;
+                }
 
-                    for aggs in aggs.values() {
-                        if let Some(predicate) = having {
-                            let value = self.complete_aggs(interpreter, aggs, predicate)?;
-                            if !matches!(value, QueryValue::Bool(true)) {
-                                continue;
-                            }
+                let order_keys = query
+                    .order_by
+                    .map(|order_by| order_keys(&interpreter.session, order_by.expr))
+                    .unwrap_or_default();
+
+                let mut orderer = query.order_by.map(|order_by| {
+                    QueryOrderer::multi(vec![order_by.order; order_keys.len()], self.nulls)
+                });
+
+                for (key, aggs) in aggs.iter() {
+                    let value = self.complete_aggs(interpreter, aggs, None, query.projection)?;
+                    // ORDER BY and HAVING can refer to the projected fields by name, like `total`
+                    let aliases = match &value {
+                        QueryValue::Record(rec) => Some(rec),
+                        _ => None,
+                    };
+
+                    if let Some(predicate) = having {
+                        let keep = self.complete_aggs(interpreter, aggs, aliases, predicate)?;
+                        if !matches!(keep, QueryValue::Bool(true)) {
+                            continue;
                         }
-
-                        let sort_key = self.complete_aggs(interpreter, aggs, order_by.expr)?;
-                        let value = self.complete_aggs(interpreter, aggs, query.projection)?;
-                        orderer.insert(sort_key, value);
                     }
 
-                    if orderer.prepare_for_streaming().is_some() {
-                        while let Some(value) = orderer.next() {
-                            self.buffer.push(value);
+                    if let Some(orderer) = orderer.as_mut() {
+                        let mut sort_keys = Vec::with_capacity(order_keys.len());
+
+                        for order_key in &order_keys {
+                            // ordering by the grouping expression itself sorts on the group key
+                            sort_keys.push(
+                                if let Some(group_by) = &query.group_by
+                                    && same_expr(&interpreter.session, *order_key, group_by.expr)
+                                {
+                                    key.clone()
+                                } else {
+                                    self.complete_aggs(interpreter, aggs, aliases, *order_key)?
+                                },
+                            );
                         }
+
+                        orderer.insert_keys(sort_keys, value);
+                    } else {
+                        self.buffer.push(value);
                     }
-                } else {
-                    for aggs in aggs.values() {
-                        let value = self.complete_aggs(interpreter, aggs, query.projection)?;
+                }
+
+                if let Some(mut orderer) = orderer
+                    && orderer.prepare_for_streaming().is_some()
+                {
+                    while let Some(value) = orderer.next() {
                         self.buffer.push(value);
                     }
                 }
@@ -212,12 +485,23 @@ impl AggEvaluator {
         &mut self,
         interpreter: &Interpreter,
         aggs: &HashMap<App, Agg>,
+        aliases: Option<&Record>,
         expr: ExprRef,
     ) -> EvalResult<QueryValue> {
         match interpreter.session.arena().get_expr(expr).value {
             Value::App(app) if aggs.contains_key(&app) => {
                 // safe: guarded by contains_key above
-                Ok(aggs[&app].complete())
+                aggs[&app].complete()
+            }
+
+            Value::Id(id) => {
+                let name = interpreter.session.arena().get_str(id);
+
+                if let Some(value) = aliases.and_then(|rec| rec.get(name)) {
+                    return Ok(value.clone());
+                }
+
+                interpreter.eval(Value::Id(id))
             }
 
             Value::Array(arr) => {
@@ -225,17 +509,17 @@ impl AggEvaluator {
                 let mut values = Vec::with_capacity(vec.len());
 
                 for expr in vec {
-                    values.push(self.complete_aggs(interpreter, aggs, *expr)?);
+                    values.push(self.complete_aggs(interpreter, aggs, aliases, *expr)?);
                 }
 
                 Ok(QueryValue::Array(values))
             }
 
             Value::Record(rec) => {
-                let mut props = BTreeMap::new();
+                let mut props = Record::new();
 
                 for field in interpreter.session.arena().get_rec(rec) {
-                    let value = self.complete_aggs(interpreter, aggs, field.expr)?;
+                    let value = self.complete_aggs(interpreter, aggs, aliases, field.expr)?;
                     let name = interpreter.session.arena().get_str(field.name);
                     props.insert(name.to_owned(), value);
                 }
@@ -244,18 +528,18 @@ impl AggEvaluator {
             }
 
             Value::Binary(binary) => {
-                let lhs = self.complete_aggs(interpreter, aggs, binary.lhs)?;
-                let rhs = self.complete_aggs(interpreter, aggs, binary.rhs)?;
+                let lhs = self.complete_aggs(interpreter, aggs, aliases, binary.lhs)?;
+                let rhs = self.complete_aggs(interpreter, aggs, aliases, binary.rhs)?;
 
                 interpreter.eval_binary(binary.operator, &lhs, &rhs)
             }
 
             Value::Unary(unary) => {
-                let value = self.complete_aggs(interpreter, aggs, unary.expr)?;
+                let value = self.complete_aggs(interpreter, aggs, aliases, unary.expr)?;
                 interpreter.eval_unary(unary.operator, &value)
             }
 
-            Value::Group(expr) => self.complete_aggs(interpreter, aggs, expr),
+            Value::Group(expr) => self.complete_aggs(interpreter, aggs, aliases, expr),
 
             x => interpreter.eval(x),
         }
@@ -264,28 +548,51 @@ impl AggEvaluator {
 
 pub struct AggQuery<'a> {
     srcs: Sources<'a>,
-    interpreter: Interpreter<'a>,
+    interpreter: Interpreter,
     query: Query<Typed>,
     layout: AggLayout,
     evaluator: AggEvaluator,
     completed: bool,
+    cancelled: bool,
+    stats: QueryStats,
     results: vec::IntoIter<QueryValue>,
 }
 
 impl<'a> AggQuery<'a> {
-    pub fn new(srcs: Sources<'a>, session: &'a Session, query: Query<Typed>) -> EvalResult<Self> {
-        let kind = AggLayout::load(session, &query)?;
+    pub fn new(
+        srcs: Sources<'a>,
+        session: Arc<Session>,
+        options: QueryOptions,
+        query: Query<Typed>,
+    ) -> EvalResult<Self> {
+        let kind = AggLayout::load(&session, options, &query)?;
 
         Ok(Self {
             srcs,
             query,
             layout: kind,
-            interpreter: Interpreter::new(session),
+            interpreter: Interpreter::new(session).with_now(options.now.unwrap_or_else(Utc::now)),
             completed: false,
+            cancelled: false,
+            stats: QueryStats::default(),
             results: Default::default(),
-            evaluator: Default::default(),
+            evaluator: AggEvaluator {
+                nulls: options.nulls,
+                strict: options.strict_aggregates,
+                gap_fill: options.gap_fill,
+                parallel: options.parallel_aggregates,
+                ..Default::default()
+            },
         })
     }
+
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.srcs.cancellation().clone()
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
 }
 
 impl<'a> Iterator for AggQuery<'a> {
@@ -293,8 +600,15 @@ impl<'a> Iterator for AggQuery<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            if self.srcs.cancellation().is_cancelled() {
+                // report the cancellation once, then stop
+                return (!mem::replace(&mut self.cancelled, true))
+                    .then_some(Err(EvalError::Cancelled));
+            }
+
             if self.completed {
                 if let Some(result) = self.results.next() {
+                    self.stats.rows_emitted += 1;
                     return Some(Ok(result));
                 }
 
@@ -312,6 +626,10 @@ impl<'a> Iterator for AggQuery<'a> {
                     return Some(Err(e));
                 }
 
+                if let AggLayout::Grouped { aggs, .. } = &self.layout {
+                    self.stats.groups = aggs.len() as u64;
+                }
+
                 let mut buffer = mem::take(&mut self.evaluator.buffer);
 
                 if self.query.distinct {
@@ -342,6 +660,8 @@ impl<'a> Iterator for AggQuery<'a> {
                 return Some(Err(e));
             }
 
+            self.stats.rows_scanned += 1;
+
             match self.interpreter.eval_predicate(&self.query) {
                 Ok(true) => {}
                 Ok(false) => continue,
@@ -354,3 +674,126 @@ impl<'a> Iterator for AggQuery<'a> {
         }
     }
 }
+
+/// Tells whether two expressions are the same field access, function call or identifier,
+/// wherever they appear in the query.
+fn same_expr(session: &Session, a: ExprRef, b: ExprRef) -> bool {
+    let arena = session.arena();
+
+    match (arena.get_expr(a).value, arena.get_expr(b).value) {
+        (Value::Access(a), Value::Access(b)) => {
+            a.field == b.field && same_expr(session, a.target, b.target)
+        }
+
+        (Value::App(a), Value::App(b)) => {
+            let (a_args, b_args) = (arena.get_vec(a.args), arena.get_vec(b.args));
+
+            a.func == b.func
+                && a_args.len() == b_args.len()
+                && a_args
+                    .iter()
+                    .zip(b_args)
+                    .all(|(a, b)| same_expr(session, *a, *b))
+        }
+
+        (a, b) => a == b,
+    }
+}
+
+/// Error of a grouped query going past its limit of `max` distinct groups.
+fn groups_exceeded(max: usize) -> EvalError {
+    EvalError::Runtime(format!("query exceeds the limit of {max} distinct groups").into())
+}
+
+/// Folds a row collected ahead of a parallel fold into the aggregates of its group.
+fn fold_row(aggs: &mut HashMap<App, Agg>, row: &FoldRow) {
+    for (app, args) in &row.args {
+        if let Some(agg) = aggs.get_mut(app) {
+            agg.fold(args);
+        }
+    }
+}
+
+/// Merges the partial aggregate states of `other` into `aggs`.
+fn merge_aggs(aggs: &mut HashMap<App, Agg>, other: HashMap<App, Agg>) -> EvalResult<()> {
+    for (app, agg) in other {
+        if let Some(current) = aggs.get_mut(&app) {
+            current.merge(&agg)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails when `expr` reads a field that doesn't exist in the accessed record, as opposed to a
+/// field that is present but null.
+fn ensure_field_exists(interpreter: &Interpreter, expr: ExprRef) -> EvalResult<()> {
+    if let Value::Access(access) = interpreter.session.arena().get_expr(expr).value
+        && let QueryValue::Record(rec) = interpreter.eval_expr(access.target)?
+    {
+        let field = interpreter.session.arena().get_str(access.field);
+
+        if rec.get(field).is_none() {
+            return Err(EvalError::Runtime(
+                format!("aggregated field '{field}' doesn't exist").into(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds an empty group for every bucket of the gap-fill range that didn't see any event. The
+/// `UNIQUE` aggregates in `seeds`, the ones over the grouping expression, are seeded with the
+/// bucket so it still shows up in the projection.
+fn fill_gaps(
+    aggs: &mut HashMap<QueryValue, HashMap<App, Agg>>,
+    base: &HashMap<App, Agg>,
+    seeds: &HashSet<App>,
+    max_groups: Option<usize>,
+    fill: GapFill,
+) -> EvalResult<()> {
+    if fill.step <= TimeDelta::zero() {
+        return Err(EvalError::Runtime("gap fill step must be positive".into()));
+    }
+
+    let range = fill.range.or_else(|| {
+        let mut buckets = aggs.keys().filter_map(|key| match key {
+            QueryValue::DateTime(t) => Some(*t),
+            _ => None,
+        });
+
+        let first = buckets.next()?;
+        Some(buckets.fold((first, first), |(lo, hi), t| (lo.min(t), hi.max(t))))
+    });
+
+    let Some((mut bucket, end)) = range else {
+        return Ok(());
+    };
+
+    while bucket <= end {
+        let count = aggs.len();
+        if let Entry::Vacant(entry) = aggs.entry(QueryValue::DateTime(bucket)) {
+            if let Some(max) = max_groups
+                && count >= max
+            {
+                return Err(groups_exceeded(max));
+            }
+
+            let mut empty = base.clone();
+            for (app, agg) in empty.iter_mut() {
+                if let Agg::Unique { .. } = agg
+                    && seeds.contains(app)
+                {
+                    agg.fold(&[QueryValue::DateTime(bucket)]);
+                }
+            }
+
+            entry.insert(empty);
+        }
+
+        bucket += fill.step;
+    }
+
+    Ok(())
+}
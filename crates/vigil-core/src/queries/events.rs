@@ -1,39 +1,95 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, mem, sync::Arc};
 
-use eventql_parser::{Limit, Order, Query, Session, prelude::Typed};
+use chrono::Utc;
+use eventql_parser::{ExprRef, Limit, Order, Query, Session, Value, prelude::Typed};
 
 use crate::queries::orderer::QueryOrderer;
 use crate::{
-    eval::{EvalResult, Interpreter},
-    queries::Sources,
+    eval::{EvalError, EvalResult, Interpreter},
+    queries::{CancellationHandle, QueryOptions, QueryStats, Sources},
     values::QueryValue,
 };
 
 pub struct EventQuery<'a> {
     srcs: Sources<'a>,
     query: Query<Typed>,
-    interpreter: Interpreter<'a>,
+    interpreter: Interpreter,
     orderer: QueryOrderer,
+    order_keys: Vec<ExprRef>,
     completed: bool,
+    cancelled: bool,
     skipped: u64,
     emitted: u64,
+    scanned: u64,
+    /// Rows already emitted by a `DISTINCT` query, so duplicates get skipped while keeping the
+    /// first-seen order. It holds every distinct row of the result, so memory is unbounded.
     seen: HashSet<QueryValue>,
 }
 
 impl<'a> EventQuery<'a> {
-    pub fn new(srcs: Sources<'a>, session: &'a Session, query: Query<Typed>) -> Self {
-        let order = query.order_by.map_or_else(|| Order::Asc, |o| o.order);
+    pub fn new(
+        srcs: Sources<'a>,
+        session: Arc<Session>,
+        options: QueryOptions,
+        query: Query<Typed>,
+    ) -> Self {
+        let orderer = match query.order_by {
+            Some(order_by) if is_rand_call(&session, order_by.expr) => {
+                QueryOrderer::shuffled(options.seed)
+            }
+
+            order_by => {
+                let keys = order_by.map_or(1, |o| order_keys(&session, o.expr).len());
+                let orderer = QueryOrderer::multi(
+                    vec![order_by.map_or_else(|| Order::Asc, |o| o.order); keys],
+                    options.nulls,
+                );
+
+                // DISTINCT drops rows once ordered, so every row has to be kept around. Bounding
+                // is pointless when the sources can't yield more than `n` rows anyway
+                match query.limit {
+                    Some(Limit::Top(n))
+                        if order_by.is_some()
+                            && !query.distinct
+                            && srcs.estimated_rows().is_none_or(|rows| rows > n as usize) =>
+                    {
+                        orderer.bounded(n as usize)
+                    }
+
+                    _ => orderer,
+                }
+            }
+        };
+
         Self {
             srcs,
+            order_keys: query
+                .order_by
+                .map(|o| order_keys(&session, o.expr))
+                .unwrap_or_default(),
             query,
-            orderer: QueryOrderer::new(order),
-            interpreter: Interpreter::new(session),
+            orderer,
+            interpreter: Interpreter::new(session).with_now(options.now.unwrap_or_else(Utc::now)),
             completed: false,
+            cancelled: false,
             skipped: 0,
             emitted: 0,
+            scanned: 0,
             seen: HashSet::new(),
         }
     }
+
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.srcs.cancellation().clone()
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        QueryStats {
+            rows_scanned: self.scanned,
+            rows_emitted: self.emitted,
+            groups: 0,
+        }
+    }
 }
 
 impl<'a> Iterator for EventQuery<'a> {
@@ -41,6 +97,12 @@ impl<'a> Iterator for EventQuery<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            if self.srcs.cancellation().is_cancelled() {
+                // report the cancellation once, then stop
+                return (!mem::replace(&mut self.cancelled, true))
+                    .then_some(Err(EvalError::Cancelled));
+            }
+
             if self.completed {
                 if let Some(Limit::Top(n)) = self.query.limit
                     && self.emitted >= n
@@ -69,6 +131,8 @@ impl<'a> Iterator for EventQuery<'a> {
                 if let Err(e) = outcome {
                     return Some(Err(e));
                 }
+
+                self.scanned += 1;
             } else {
                 self.completed = true;
                 self.orderer.prepare_for_streaming()?;
@@ -82,10 +146,20 @@ impl<'a> Iterator for EventQuery<'a> {
                 Err(e) => return Some(Err(e)),
             }
 
-            if let Some(order_by) = &self.query.order_by {
-                let key = match self.interpreter.eval_expr(order_by.expr) {
-                    Err(e) => return Some(Err(e)),
-                    Ok(key) => key,
+            if self.query.order_by.is_some() {
+                // a shuffle doesn't need keys, every row goes into the same batch
+                let keys = if self.orderer.is_shuffled() {
+                    vec![QueryValue::Null]
+                } else {
+                    match self
+                        .order_keys
+                        .iter()
+                        .map(|key| self.interpreter.eval_expr(*key))
+                        .collect::<EvalResult<Vec<_>>>()
+                    {
+                        Err(e) => return Some(Err(e)),
+                        Ok(keys) => keys,
+                    }
                 };
 
                 let value = match self.interpreter.eval_expr(self.query.projection) {
@@ -93,7 +167,7 @@ impl<'a> Iterator for EventQuery<'a> {
                     Ok(v) => v,
                 };
 
-                self.orderer.insert(key, value);
+                self.orderer.insert_keys(keys, value);
                 continue;
             }
 
@@ -124,3 +198,26 @@ impl<'a> Iterator for EventQuery<'a> {
         }
     }
 }
+
+/// Tells if `expr` is a bare `RAND()` call, which orders rows by shuffling them.
+pub(crate) fn is_rand_call(session: &Session, expr: ExprRef) -> bool {
+    if let Value::App(app) = session.arena().get_expr(expr).value {
+        return session
+            .arena()
+            .get_str(app.func)
+            .eq_ignore_ascii_case("rand")
+            && session.arena().get_vec(app.args).is_empty();
+    }
+
+    false
+}
+
+/// Keys an `ORDER BY` clause sorts on. An array literal, as in `ORDER BY [e.data.dept,
+/// e.data.salary]`, sorts on each of its elements in turn, anything else is a single key. The
+/// clause carries a single direction, which applies to every key.
+pub(crate) fn order_keys(session: &Session, expr: ExprRef) -> Vec<ExprRef> {
+    match session.arena().get_expr(expr).value {
+        Value::Array(keys) => session.arena().get_vec(keys).to_vec(),
+        _ => vec![expr],
+    }
+}
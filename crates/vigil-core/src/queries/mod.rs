@@ -3,15 +3,86 @@ use crate::{
     queries::{aggregates::AggQuery, events::EventQuery},
     values::QueryValue,
 };
+use chrono::{DateTime, TimeDelta, Utc};
 use eventql_parser::StrRef;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::Write,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 pub mod aggregates;
 pub mod events;
-mod orderer;
+pub(crate) mod orderer;
 
 pub type Buffer = HashMap<StrRef, QueryValue>;
 
+/// Knobs tweaking how a query gets executed, independently of the query text itself.
+#[derive(Default, Clone, Copy)]
+pub struct QueryOptions {
+    /// Trims and lowercases string `GROUP BY` keys so `"Sales "` and `"sales"` end up in the same
+    /// group.
+    pub normalize_group_keys: bool,
+
+    /// Where rows with a `Null` `ORDER BY` key end up.
+    pub nulls: NullsPlacement,
+
+    /// Seeds the shuffle behind `ORDER BY RAND()`, making its permutation reproducible.
+    pub seed: Option<u64>,
+
+    /// Instant every `NOW()` call of a query returns, subqueries included. Defaults to when the
+    /// query starts running.
+    pub now: Option<DateTime<Utc>>,
+
+    /// Fails aggregates whose argument reads a field missing from the record, instead of
+    /// silently aggregating `Null`. A field present with a `null` value is still accepted.
+    pub strict_aggregates: bool,
+
+    /// Emits an empty group for every time bucket without events in a query grouped by a
+    /// `DateTime` bucket, like `GROUP BY DATE_TRUNC("hour", e.time)`.
+    pub gap_fill: Option<GapFill>,
+
+    /// Maximum number of rows a query pulls from its sources, subqueries and join combinations
+    /// included. Tripping it fails the query with [`EvalError::RowBudgetExceeded`].
+    pub row_budget: Option<u64>,
+
+    /// Maximum number of distinct keys a `GROUP BY` query keeps in memory. Seeing one more key
+    /// fails the query instead of growing the group table further.
+    pub max_groups: Option<usize>,
+
+    /// Folds aggregates on the rayon thread pool. Rows are still pulled and evaluated in order,
+    /// and buffered in bounded batches. Each batch is split into one partition per thread whose
+    /// partial states get merged back in order.
+    pub parallel_aggregates: bool,
+}
+
+/// Time buckets expected in the output of a time-bucketed grouped query.
+#[derive(Clone, Copy)]
+pub struct GapFill {
+    /// Width of a bucket, matching the one used in the `GROUP BY` clause.
+    pub step: TimeDelta,
+
+    /// First and last buckets, both inclusive. When missing, the range spans from the earliest to
+    /// the latest bucket seen in the data.
+    pub range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// Placement of `Null` keys in an ordered output, as in `NULLS FIRST` / `NULLS LAST`.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum NullsPlacement {
+    /// `Null` sorts below every other value: first when ascending, last when descending.
+    #[default]
+    Natural,
+    First,
+    Last,
+}
+
 pub enum QueryProcessor<'a> {
     Regular(EventQuery<'a>),
     Aggregate(AggQuery<'a>),
@@ -30,6 +101,106 @@ impl<'a> QueryProcessor<'a> {
     {
         Self::Generic(Box::new(proc))
     }
+
+    /// Transforms every row with `f`. Errors from the query go through untouched.
+    pub fn map_rows<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(QueryValue) -> EvalResult<QueryValue> + 'a,
+    {
+        Self::generic(self.map(move |row| f(row?)))
+    }
+
+    /// Only keeps the rows `p` is true for. Errors from the query are always kept.
+    pub fn filter_rows<P>(self, mut p: P) -> Self
+    where
+        P: FnMut(&QueryValue) -> bool + 'a,
+    {
+        Self::generic(self.filter(move |row| row.as_ref().map_or(true, &mut p)))
+    }
+
+    /// Handle stopping this query, subqueries included, from anywhere, another thread included.
+    /// Processors not backed by a query, like the ones of named data sources, have none.
+    pub fn cancellation_handle(&self) -> Option<CancellationHandle> {
+        match self {
+            QueryProcessor::Regular(query) => Some(query.cancellation_handle()),
+            QueryProcessor::Aggregate(query) => Some(query.cancellation_handle()),
+            QueryProcessor::Errored(_) | QueryProcessor::Generic(_) => None,
+        }
+    }
+
+    /// Counters of the query, final once it's exhausted. Processors not backed by a query have
+    /// none.
+    pub fn stats(&self) -> Option<QueryStats> {
+        match self {
+            QueryProcessor::Regular(query) => Some(query.stats()),
+            QueryProcessor::Aggregate(query) => Some(query.stats()),
+            QueryProcessor::Errored(_) | QueryProcessor::Generic(_) => None,
+        }
+    }
+
+    /// Streams every row to `w` as a line of plain JSON (see [`crate::values::PlainJson`]). Stops
+    /// at the first query error.
+    pub fn into_json_writer<W: Write>(self, mut w: W) -> EvalResult<()> {
+        for row in self {
+            serde_json::to_writer(&mut w, &row?.plain_json()).map_err(|e| {
+                EvalError::Runtime(format!("failed to write query results: {e}").into())
+            })?;
+
+            w.write_all(b"\n").map_err(|e| {
+                EvalError::Runtime(format!("failed to write query results: {e}").into())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes rows as CSV, or TSV with a `b'\t'` delimiter. Every row has to be a record with the
+    /// same fields as the first one, whose names make the header. Nested records and arrays are
+    /// JSON-encoded inside their cell.
+    pub fn into_csv_writer<W: Write>(self, w: W, delimiter: u8) -> EvalResult<()> {
+        let io_err = |e: csv::Error| {
+            EvalError::Runtime(format!("failed to write query results: {e}").into())
+        };
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(w);
+        let mut header = None::<Vec<String>>;
+
+        for row in self {
+            let QueryValue::Record(record) = row? else {
+                return Err(EvalError::Runtime(
+                    "CSV output requires every row to be a record".into(),
+                ));
+            };
+
+            match &header {
+                None => {
+                    let names = record
+                        .iter()
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<_>>();
+                    writer.write_record(&names).map_err(io_err)?;
+                    header = Some(names);
+                }
+
+                Some(names) => {
+                    if !record.iter().map(|(name, _)| name).eq(names.iter()) {
+                        return Err(EvalError::Runtime(
+                            "CSV output requires every record to have the same fields".into(),
+                        ));
+                    }
+                }
+            }
+
+            writer
+                .write_record(record.iter().map(|(_, value)| csv_cell(value)))
+                .map_err(io_err)?;
+        }
+
+        writer
+            .flush()
+            .map_err(|e| EvalError::Runtime(format!("failed to write query results: {e}").into()))
+    }
 }
 
 impl<'a> Iterator for QueryProcessor<'a> {
@@ -45,31 +216,231 @@ impl<'a> Iterator for QueryProcessor<'a> {
     }
 }
 
+/// Rows left to pull, shared by every processor of a query so nested subqueries draw from the same
+/// budget.
+#[derive(Clone, Default)]
+pub struct RowBudget {
+    remaining: Option<Rc<Cell<u64>>>,
+}
+
+impl RowBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            remaining: limit.map(|n| Rc::new(Cell::new(n))),
+        }
+    }
+
+    fn consume(&self) -> EvalResult<()> {
+        if let Some(remaining) = &self.remaining {
+            if remaining.get() == 0 {
+                return Err(EvalError::RowBudgetExceeded);
+            }
+
+            remaining.set(remaining.get() - 1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Flag stopping a running query. Once cancelled, the query yields [`EvalError::Cancelled`] on its
+/// next pull, then nothing.
+#[derive(Clone, Default)]
+pub struct CancellationHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// What a query went through so far. Counters only cover the query itself, not its subqueries.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryStats {
+    /// Rows pulled from the sources, before the predicate filters them. A join counts every
+    /// combination it tries.
+    pub rows_scanned: u64,
+    pub rows_emitted: u64,
+    /// Distinct keys of a `GROUP BY` query, known once every row got folded.
+    pub groups: u64,
+}
+
+/// Description of how a query would run, as returned by [`crate::databases::in_mem::InMemDb::explain`].
+/// Building it doesn't execute anything.
+#[derive(Serialize)]
+pub struct QueryPlan {
+    pub sources: Vec<SourcePlan>,
+    /// Whether rows get folded by aggregate functions instead of streamed one by one.
+    pub aggregate: bool,
+    pub grouped: bool,
+    pub having: bool,
+    pub distinct: bool,
+    pub order: Option<PlanOrder>,
+    pub limit: Option<PlanLimit>,
+}
+
+#[derive(Serialize)]
+pub enum SourcePlan {
+    Named {
+        binding: String,
+        name: String,
+        /// Event types the scan is narrowed to, when the predicate only lets those through.
+        event_types: Option<Vec<String>>,
+    },
+    Subject {
+        binding: String,
+        subject: String,
+    },
+    Subquery {
+        binding: String,
+        plan: Box<QueryPlan>,
+    },
+}
+
+#[derive(Serialize)]
+pub enum PlanOrder {
+    Asc,
+    Desc,
+    /// `ORDER BY RAND()`.
+    Shuffled,
+}
+
+#[derive(Serialize)]
+pub enum PlanLimit {
+    Skip(u64),
+    Top(u64),
+}
+
+/// The sources of a query. A single source streams its rows as-is. Several sources are joined:
+/// every source but the first one is materialized, then each row of the first source is paired
+/// with every combination of the others. The query predicate filtering those combinations is what
+/// turns the cartesian product into an inner join.
 #[derive(Default)]
 pub struct Sources<'a> {
-    inner: HashMap<StrRef, QueryProcessor<'a>>,
+    inner: Vec<(StrRef, QueryProcessor<'a>)>,
+    joined: Option<Vec<Vec<QueryValue>>>,
+    /// Row of the first source the current combinations are built upon. Consumers clear their
+    /// buffer between pulls, so it has to be written back on every one of them.
+    outer: Option<QueryValue>,
+    cursor: Vec<usize>,
+    started: bool,
+    budget: RowBudget,
+    cancellation: CancellationHandle,
+    estimate: Option<usize>,
 }
 
 impl<'a> Sources<'a> {
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&StrRef, &mut QueryProcessor<'a>)> {
-        self.inner.iter_mut()
+    pub fn new(budget: RowBudget, cancellation: CancellationHandle) -> Self {
+        Self {
+            budget,
+            cancellation,
+            ..Default::default()
+        }
+    }
+
+    pub fn cancellation(&self) -> &CancellationHandle {
+        &self.cancellation
+    }
+
+    /// Adds a source, along with the number of rows it yields when known.
+    pub fn insert(&mut self, key: StrRef, proc: QueryProcessor<'a>, estimate: Option<usize>) {
+        self.estimate = if self.inner.is_empty() {
+            estimate
+        } else {
+            self.estimate
+                .zip(estimate)
+                .and_then(|(rows, other)| rows.checked_mul(other))
+        };
+
+        self.inner.push((key, proc));
     }
 
-    pub fn insert(&mut self, key: StrRef, proc: QueryProcessor<'a>) {
-        self.inner.insert(key, proc);
+    /// Number of rows, join combinations included, the sources yield when every one of them
+    /// knows its own.
+    pub fn estimated_rows(&self) -> Option<usize> {
+        self.estimate
     }
 
     pub fn fill(&mut self, buffer: &mut Buffer) -> Option<EvalResult<()>> {
-        for (binding, row) in self.iter_mut() {
-            match row.next()? {
-                Ok(value) => {
-                    buffer.insert(*binding, value);
+        if self.joined.is_none() {
+            let mut joined = Vec::with_capacity(self.inner.len().saturating_sub(1));
+
+            for (_, proc) in self.inner.iter_mut().skip(1) {
+                match proc.collect::<EvalResult<Vec<_>>>() {
+                    Ok(rows) => joined.push(rows),
+                    Err(e) => return Some(Err(e)),
                 }
+            }
 
+            self.cursor = vec![0; joined.len()];
+            self.joined = Some(joined);
+        }
+
+        let joined = self.joined.as_ref()?;
+        if joined.iter().any(Vec::is_empty) {
+            return None;
+        }
+
+        if !advance(&mut self.cursor, joined, &mut self.started) {
+            let (_, outer) = self.inner.first_mut()?;
+
+            match outer.next()? {
+                Ok(value) => self.outer = Some(value),
                 Err(e) => return Some(Err(e)),
             }
         }
 
-        Some(Ok(()))
+        let (binding, _) = self.inner.first()?;
+        buffer.insert(*binding, self.outer.clone()?);
+
+        for ((binding, _), (rows, pos)) in self
+            .inner
+            .iter()
+            .skip(1)
+            .zip(joined.iter().zip(&self.cursor))
+        {
+            buffer.insert(*binding, rows[*pos].clone());
+        }
+
+        Some(self.budget.consume())
+    }
+}
+
+/// Renders a value as a CSV cell: `Null` is an empty cell and nested values are JSON-encoded.
+fn csv_cell(value: &QueryValue) -> String {
+    match value {
+        QueryValue::Null => String::new(),
+        QueryValue::String(s) => s.clone(),
+        QueryValue::DateTime(t) => t.to_rfc3339(),
+        QueryValue::Date(d) => d.to_string(),
+        QueryValue::Time(t) => t.to_string(),
+        value => serde_json::to_string(&value.plain_json()).unwrap_or_default(),
+    }
+}
+
+/// Moves to the next combination of materialized rows. Returns `false` when they are exhausted,
+/// meaning the first source has to move to its next row.
+fn advance(cursor: &mut [usize], joined: &[Vec<QueryValue>], started: &mut bool) -> bool {
+    if !*started {
+        *started = true;
+        return false;
     }
+
+    for (pos, rows) in cursor.iter_mut().zip(joined).rev() {
+        *pos += 1;
+
+        if *pos < rows.len() {
+            return true;
+        }
+
+        *pos = 0;
+    }
+
+    false
 }
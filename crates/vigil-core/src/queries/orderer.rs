@@ -1,27 +1,163 @@
-use crate::values::QueryValue;
+use crate::{queries::NullsPlacement, values::QueryValue};
 use eventql_parser::Order;
-use std::collections::BTreeMap;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use std::{cmp::Reverse, collections::BTreeMap};
+
+/// One component of a composite order key. Components sorting against the direction of the
+/// first key are flipped, so the whole map can be walked in the first key's direction.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Same(QueryValue),
+    Flipped(Reverse<QueryValue>),
+}
+
+type Batches = Box<dyn Iterator<Item = Vec<QueryValue>>>;
+
+/// Rows kept by a bounded orderer. Keys are arranged so the map walks in output order: the rank
+/// places `Null` first keys per [`NullsPlacement`] and every component is flipped when descending.
+struct TopRows {
+    limit: usize,
+    len: usize,
+    rows: BTreeMap<(u8, Vec<SortKey>), Vec<QueryValue>>,
+}
 
 pub struct QueryOrderer {
     order: Order,
-    order_map: Option<BTreeMap<QueryValue, Vec<QueryValue>>>,
-    order_iter: Option<Box<dyn Iterator<Item = Vec<QueryValue>>>>,
+    directions: Vec<Order>,
+    nulls: NullsPlacement,
+    shuffle: Option<Box<StdRng>>,
+    order_map: Option<BTreeMap<Vec<SortKey>, Vec<QueryValue>>>,
+    top: Option<TopRows>,
+    order_iter: Option<Batches>,
     batch_iter: Option<Box<dyn Iterator<Item = QueryValue>>>,
 }
 
 impl QueryOrderer {
-    pub fn new(order: Order) -> Self {
+    pub fn new(order: Order, nulls: NullsPlacement) -> Self {
+        Self::multi(vec![order], nulls)
+    }
+
+    /// Orders by several keys, each with its own direction, comparing them lexicographically.
+    /// `nulls` applies to the first key.
+    pub fn multi(directions: Vec<Order>, nulls: NullsPlacement) -> Self {
         Self {
-            order,
+            order: directions.first().copied().unwrap_or(Order::Asc),
+            directions,
+            nulls,
+            shuffle: None,
             order_map: Some(BTreeMap::new()),
+            top: None,
             order_iter: None,
             batch_iter: None,
         }
     }
 
+    /// Streams values back in a random order, Fisher-Yates shuffled once all of them are buffered.
+    pub fn shuffled(seed: Option<u64>) -> Self {
+        let rng = seed.map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64);
+
+        Self {
+            shuffle: Some(Box::new(rng)),
+            ..Self::new(Order::Asc, NullsPlacement::Natural)
+        }
+    }
+
+    /// Only keeps the first `limit` rows of the output, as in `ORDER BY ... TOP n`. Rows falling
+    /// past the limit get dropped as they come in, so at most `limit` rows are ever buffered.
+    pub fn bounded(self, limit: usize) -> Self {
+        Self {
+            order_map: None,
+            top: Some(TopRows {
+                limit,
+                len: 0,
+                rows: BTreeMap::new(),
+            }),
+            ..self
+        }
+    }
+
+    /// Number of rows buffered so far.
+    #[cfg(test)]
+    pub fn buffered(&self) -> usize {
+        if let Some(top) = &self.top {
+            return top.len;
+        }
+
+        self.order_map
+            .as_ref()
+            .map_or(0, |map| map.values().map(Vec::len).sum())
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle.is_some()
+    }
+
+    #[cfg(test)]
     pub fn insert(&mut self, key: QueryValue, value: QueryValue) {
+        self.insert_keys(vec![key], value);
+    }
+
+    pub fn insert_keys(&mut self, keys: Vec<QueryValue>, value: QueryValue) {
+        if self.top.is_some() {
+            self.insert_top(keys, value);
+            return;
+        }
+
+        let primary_asc = matches!(self.order, Order::Asc);
+        let keys = keys
+            .into_iter()
+            .zip(&self.directions)
+            .map(|(key, direction)| {
+                if matches!(direction, Order::Asc) == primary_asc {
+                    SortKey::Same(key)
+                } else {
+                    SortKey::Flipped(Reverse(key))
+                }
+            })
+            .collect();
+
         if let Some(order_map) = self.order_map.as_mut() {
-            order_map.entry(key).or_default().push(value);
+            order_map.entry(keys).or_default().push(value);
+        }
+    }
+
+    fn insert_top(&mut self, keys: Vec<QueryValue>, value: QueryValue) {
+        let rank = match (self.nulls, keys.first()) {
+            (NullsPlacement::First, Some(QueryValue::Null)) => 0,
+            (NullsPlacement::First, _) => 1,
+            (NullsPlacement::Last, Some(QueryValue::Null)) => 1,
+            _ => 0,
+        };
+
+        let keys = keys
+            .into_iter()
+            .zip(&self.directions)
+            .map(|(key, direction)| match direction {
+                Order::Asc => SortKey::Same(key),
+                Order::Desc => SortKey::Flipped(Reverse(key)),
+            })
+            .collect();
+
+        let Some(top) = self.top.as_mut() else {
+            return;
+        };
+
+        if top.limit == 0 {
+            return;
+        }
+
+        top.rows.entry((rank, keys)).or_default().push(value);
+        top.len += 1;
+
+        if top.len > top.limit
+            && let Some(mut last) = top.rows.last_entry()
+        {
+            last.get_mut().pop();
+            top.len -= 1;
+
+            if last.get().is_empty() {
+                last.remove();
+            }
         }
     }
 
@@ -39,13 +175,44 @@ impl QueryOrderer {
     }
 
     pub fn prepare_for_streaming(&mut self) -> Option<()> {
-        let map = self.order_map.take()?;
-        let mut order_iter: Box<dyn Iterator<Item = Vec<QueryValue>>> =
-            if matches!(self.order, Order::Asc) {
-                Box::new(map.into_values())
-            } else {
-                Box::new(map.into_values().rev())
-            };
+        if let Some(top) = self.top.take() {
+            // the map already walks in output order
+            let mut order_iter: Batches = Box::new(top.rows.into_values());
+
+            let next_batch = order_iter.next()?;
+            self.order_iter = Some(order_iter);
+            self.set_next_batch(next_batch);
+
+            return Some(());
+        }
+
+        let mut map = self.order_map.take()?;
+
+        if let Some(rng) = self.shuffle.as_mut() {
+            for batch in map.values_mut() {
+                batch.shuffle(rng.as_mut());
+            }
+        }
+
+        let (nulls, values): (Vec<_>, Vec<_>) = map.into_iter().partition(|(keys, _)| {
+            // the first key is never flipped
+            !matches!(self.nulls, NullsPlacement::Natural)
+                && keys.first() == Some(&SortKey::Same(QueryValue::Null))
+        });
+
+        let nulls = nulls.into_iter().map(|(_, batch)| batch);
+        let values = values.into_iter().map(|(_, batch)| batch);
+        let (nulls, values): (Batches, Batches) = if matches!(self.order, Order::Asc) {
+            (Box::new(nulls), Box::new(values))
+        } else {
+            (Box::new(nulls.rev()), Box::new(values.rev()))
+        };
+
+        let mut order_iter: Batches = if matches!(self.nulls, NullsPlacement::Last) {
+            Box::new(values.chain(nulls))
+        } else {
+            Box::new(nulls.chain(values))
+        };
 
         let next_batch = order_iter.next()?;
         self.order_iter = Some(order_iter);
@@ -54,13 +221,8 @@ impl QueryOrderer {
         Some(())
     }
 
+    /// Rows sharing the same keys always stream in insertion order, whatever the direction.
     fn set_next_batch(&mut self, values: Vec<QueryValue>) {
-        let batch: Box<dyn Iterator<Item = QueryValue>> = if matches!(self.order, Order::Asc) {
-            Box::new(values.into_iter())
-        } else {
-            Box::new(values.into_iter().rev())
-        };
-
-        self.batch_iter = Some(batch);
+        self.batch_iter = Some(Box::new(values.into_iter()));
     }
 }
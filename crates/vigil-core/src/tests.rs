@@ -1,7 +1,19 @@
+use eventql_parser::{Order, prelude::Operator};
 use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    sync::{Arc, RwLock},
+};
 use uuid::uuid;
 
-use crate::{databases::in_mem::InMemDb, eval::EvalResult, types::Event, values::QueryValue};
+use crate::{
+    databases::{Error, in_mem::InMemDb},
+    eval::{EvalError, EvalResult, Interpreter},
+    planner::{DataProvider, DataSource},
+    queries::{GapFill, NullsPlacement, QueryStats, aggregates::agg::Agg, orderer::QueryOrderer},
+    types::{Decoders, Event},
+    values::{QueryValue, Record},
+};
 
 fn load_departments_dataset(db: &mut InMemDb) {
     #[derive(Deserialize)]
@@ -29,6 +41,80 @@ fn load_departments_dataset(db: &mut InMemDb) {
     }
 }
 
+fn load_timestamps_dataset(db: &mut InMemDb, timestamps: &[&str]) {
+    for ts in timestamps {
+        db.append(
+            "sensors/probe",
+            vec![Event {
+                event_type: "measured".to_string(),
+                datacontenttype: "application/json".to_string(),
+                data: serde_json::to_vec(&serde_json::json!({ "ts": ts })).unwrap(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+}
+
+fn load_payroll_dataset(db: &mut InMemDb) {
+    for payload in [
+        serde_json::json!({ "name": "a", "bonus": 10 }),
+        serde_json::json!({ "name": "b" }),
+        serde_json::json!({ "name": "c", "bonus": 5 }),
+        serde_json::json!({ "name": "d" }),
+    ] {
+        db.append(
+            "payroll",
+            vec![Event {
+                event_type: "bonus-granted".to_string(),
+                datacontenttype: "application/json".to_string(),
+                data: serde_json::to_vec(&payload).unwrap(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+}
+
+fn load_bonuses_dataset(db: &mut InMemDb) {
+    for (employee, amount) in [(2, 5000), (5, 1000), (42, 300)] {
+        db.append(
+            "bonuses",
+            vec![Event {
+                event_type: "bonus-granted".to_string(),
+                datacontenttype: "application/json".to_string(),
+                data: serde_json::to_vec(&serde_json::json!({
+                    "employee": employee,
+                    "amount": amount,
+                }))
+                .unwrap(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+}
+
+fn load_csv_dataset(db: &mut InMemDb) {
+    for (event_type, data) in [
+        ("order-placed", "item,qty,price\nwidget,3,12.5\n"),
+        ("order-placed", "item,qty,price\nbolt,10,0.25\n"),
+        ("batch-imported", "item,qty\nnut,100\nwasher,250\n"),
+        ("batch-imported", "item,qty\nscrew,5,extra\n"),
+    ] {
+        db.append(
+            "warehouse/orders",
+            vec![Event {
+                event_type: event_type.to_string(),
+                datacontenttype: "text/csv".to_string(),
+                data: data.as_bytes().to_vec(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+}
+
 #[test]
 fn test_append() {
     let mut db = InMemDb::default();
@@ -42,266 +128,2852 @@ fn test_append() {
 }
 
 #[test]
-fn test_illegal_subject() {
+fn test_append_default_content_type() {
+    let mut db = InMemDb::default();
+
+    db.set_default_content_type(Some("application/json".to_string()));
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            data: serde_json::to_vec(&serde_json::json!({ "name": "krispy" })).unwrap(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_default_content_type.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_event_builder() {
+    let event = Event::builder()
+        .subject("companies/krispy")
+        .event_type("user-created")
+        .json_data(&serde_json::json!({ "name": "krispy" }))
+        .unwrap()
+        .build();
+
+    assert_eq!(event.datacontenttype.as_str(), "application/json");
+    assert_eq!(event.data.as_slice(), br#"{"name":"krispy"}"#);
+    assert_eq!(event.subject.as_str(), "companies/krispy");
+    assert_eq!(event.event_type.as_str(), "user-created");
+    assert_eq!(event.spec_version.as_str(), "1.0");
+    assert!(!event.id.is_nil());
+
+    let id = uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d");
+    assert_eq!(Event::builder().id(id).build().id, id);
+}
+
+#[test]
+fn test_ingest_ndjson() {
+    let mut db = InMemDb::default();
+
+    let ingested = db
+        .ingest_ndjson(
+            r#"{"subject": "krispy/nyc", "type": "user-created", "payload": {"name": "sarah"}}
+
+{"subject": "krispy/boston", "type": "user-created", "payload": {"name": "james"}}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+    assert_eq!(ingested, 2);
+    assert_eq!(db.iter_subject_events("krispy").count(), 2);
+    assert_eq!(
+        db.get(1).unwrap().datacontenttype.as_str(),
+        "application/json"
+    );
+
+    match db.ingest_ndjson(
+        r#"{"subject": "krispy/nyc", "type": "user-created", "payload": {}}
+{"subject": "krispy/nyc", "type": "user-created""#
+            .as_bytes(),
+    ) {
+        Err(Error::MalformedNdjson { line, .. }) => assert_eq!(line, 2),
+        _ => panic!("expected a malformed NDJSON error"),
+    }
+}
+
+#[test]
+fn test_illegal_subject() {
+    let mut db = InMemDb::default();
+    insta::assert_yaml_snapshot!(db.append(
+        "/path/to/file",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            ..Default::default()
+        }]
+    ));
+}
+
+#[test]
+fn test_run_query_from_events() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            id: uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d"),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            event_type: "user-deleted".to_string(),
+            id: uuid!("77344193-67bb-44af-a854-77d5d56dbb3d"),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_from_events.eql"))
+            .unwrap()
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_delete_event() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "companies/krispy",
+        vec![
+            Event {
+                event_type: "user-created".to_string(),
+                id: uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d"),
+                ..Default::default()
+            },
+            Event {
+                event_type: "user-created".to_string(),
+                id: uuid!("77344193-67bb-44af-a854-77d5d56dbb3d"),
+                ..Default::default()
+            },
+        ],
+    )
+    .unwrap();
+
+    assert!(
+        db.delete(
+            "companies/krispy",
+            uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d")
+        )
+        .unwrap()
+    );
+    assert!(
+        !db.delete(
+            "companies/krispy",
+            uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d")
+        )
+        .unwrap()
+    );
+    assert!(db.get(0).is_none());
+    assert_eq!(db.iter_types("user-created").count(), 1);
+    assert_eq!(db.iter_subject_events("companies").count(), 1);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_deleted_events.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_event_validation() {
+    let mut db = InMemDb::default();
+    let event = |event_type: &str, source: &str| {
+        Event::builder()
+            .event_type(event_type)
+            .source(source)
+            .build()
+    };
+
+    // disabled by default
+    db.append("companies/krispy", vec![event("", "")]).unwrap();
+
+    db.set_event_validation(true);
+
+    for source in [
+        "https://krispy.com/hr",
+        "/hr/payroll",
+        "urn:krispy:hr",
+        "hr",
+    ] {
+        db.append("companies/krispy", vec![event("user-created", source)])
+            .unwrap();
+    }
+
+    assert!(matches!(
+        db.append(
+            "companies/krispy",
+            vec![event("user-created", "/hr"), event("", "/hr")],
+        ),
+        Err(Error::InvalidEvent(_))
+    ));
+
+    for source in ["", "hr payroll", "1http://krispy.com", "/hr%2"] {
+        assert!(matches!(
+            db.append("companies/krispy", vec![event("user-created", source)]),
+            Err(Error::InvalidEvent(_))
+        ));
+    }
+
+    // the rejected batch left nothing behind
+    assert_eq!(db.iter_subject_events("companies/krispy").count(), 5);
+
+    // events built by hand get the spec version filled in
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            source: "/hr".to_string(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        db.iter_subject_events("companies/krispy")
+            .last()
+            .unwrap()
+            .spec_version,
+        "1.0"
+    );
+
+    let mut legacy = event("user-created", "/hr");
+    legacy.spec_version = "0.3".to_string();
+    assert!(matches!(
+        db.append("companies/krispy", vec![legacy]),
+        Err(Error::InvalidEvent(_))
+    ));
+}
+
+#[test]
+fn test_event_validation_disabled_stores_events_as_is() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    let event = db.iter_subject_events("companies/krispy").next().unwrap();
+
+    assert_eq!(event.event_type, "user-created");
+    assert!(event.spec_version.is_empty());
+}
+
+#[test]
+fn test_get_and_delete_by_id() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "companies/krispy",
+        vec![
+            Event {
+                event_type: "user-created".to_string(),
+                id: uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d"),
+                ..Default::default()
+            },
+            Event {
+                event_type: "user-updated".to_string(),
+                id: uuid!("77344193-67bb-44af-a854-77d5d56dbb3d"),
+                ..Default::default()
+            },
+        ],
+    )
+    .unwrap();
+
+    db.append(
+        "companies/acme",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            id: uuid!("a5b2c9d4-3e1f-4a6b-8c7d-9e0f1a2b3c4d"),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        db.get_by_id(uuid!("77344193-67bb-44af-a854-77d5d56dbb3d"))
+            .unwrap()
+            .event_type,
+        "user-updated"
+    );
+    assert_eq!(
+        db.get_by_id(uuid!("a5b2c9d4-3e1f-4a6b-8c7d-9e0f1a2b3c4d"))
+            .unwrap()
+            .event_type,
+        "user-created"
+    );
+    assert!(
+        db.get_by_id(uuid!("00000000-0000-4000-8000-000000000000"))
+            .is_none()
+    );
+
+    assert!(db.delete_by_id(uuid!("a5b2c9d4-3e1f-4a6b-8c7d-9e0f1a2b3c4d")));
+    assert!(!db.delete_by_id(uuid!("a5b2c9d4-3e1f-4a6b-8c7d-9e0f1a2b3c4d")));
+    assert!(
+        db.get_by_id(uuid!("a5b2c9d4-3e1f-4a6b-8c7d-9e0f1a2b3c4d"))
+            .is_none()
+    );
+    assert_eq!(db.iter_subject_events("companies").count(), 2);
+}
+
+#[test]
+fn test_run_query_whole_event() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "companies/krispy",
+        vec![Event {
+            spec_version: "1.0".to_string(),
+            id: uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d"),
+            source: "vigil".to_string(),
+            subject: "companies/krispy".to_string(),
+            event_type: "user-created".to_string(),
+            datacontenttype: "application/json".to_string(),
+            data: serde_json::to_vec(&serde_json::json!({ "name": "krispy" })).unwrap(),
+            ingested_at: "2024-01-01T08:00:00Z".parse().unwrap(),
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_whole_event.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_invalid_utf8_data() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "notes",
+        vec![
+            Event::builder()
+                .event_type("note-taken")
+                .data("text/plain", b"hello".to_vec())
+                .build(),
+            Event::builder()
+                .event_type("note-taken")
+                .data("text/plain", vec![0x68, 0xff, 0xfe, 0x6f])
+                .build(),
+        ],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_invalid_utf8_data.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_data_base64() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "blobs",
+        vec![
+            Event::builder()
+                .event_type("blob-uploaded")
+                .data(
+                    "application/octet-stream",
+                    vec![0x00, 0x01, 0x02, 0xff, 0xfe],
+                )
+                .build(),
+        ],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_data_base64.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_custom_decoder() {
+    let mut db = InMemDb::default();
+
+    db.register_decoder("text/plain", |data| {
+        let text = str::from_utf8(data).ok()?;
+
+        Some(serde_json::json!({ "text": text, "length": text.len() }))
+    });
+
+    db.append(
+        "notes",
+        vec![
+            Event::builder()
+                .event_type("note-taken")
+                .data("text/plain", b"buy milk".to_vec())
+                .build(),
+            Event::builder()
+                .event_type("note-taken")
+                .data("text/plain", vec![0xff])
+                .build(),
+        ],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_custom_decoder.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_decoders_shared_across_threads() {
+    let mut decoders = Decoders::default();
+
+    decoders.register("text/plain", |data| {
+        str::from_utf8(data).ok().map(serde_json::Value::from)
+    });
+
+    let decoded = std::thread::scope(|scope| {
+        scope
+            .spawn(|| decoders.get("text/plain").unwrap()(b"hello"))
+            .join()
+            .unwrap()
+    });
+
+    assert_eq!(decoded, Some(serde_json::json!("hello")));
+}
+
+#[test]
+fn test_projection_cache_matches_uncached() {
+    let queries = [
+        include_str!("./resources/query_order_by.eql"),
+        include_str!("./resources/department-grouping-ordered.eql"),
+        include_str!("./resources/query_whole_event.eql"),
+    ];
+
+    let mut db = InMemDb::default();
+    load_departments_dataset(&mut db);
+
+    let mut uncached = Vec::new();
+    for query in queries {
+        uncached.push(
+            db.run_query(query)
+                .unwrap()
+                .collect::<EvalResult<Vec<_>>>()
+                .unwrap(),
+        );
+    }
+
+    db.set_projection_cache(1_000);
+
+    // the second round is served from the cache filled by the first one
+    for _ in 0..2 {
+        for (query, expected) in queries.iter().zip(&uncached) {
+            let cached = db
+                .run_query(query)
+                .unwrap()
+                .collect::<EvalResult<Vec<_>>>()
+                .unwrap();
+
+            assert!(&cached == expected);
+        }
+    }
+}
+
+#[test]
+fn test_projection_cache_is_bounded() {
+    let mut db = InMemDb::default();
+    load_departments_dataset(&mut db);
+
+    let expected = db
+        .run_query(include_str!("./resources/query_whole_event.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    db.set_projection_cache(4);
+
+    for _ in 0..2 {
+        let cached = db
+            .run_query(include_str!("./resources/query_whole_event.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+            .unwrap();
+
+        assert!(cached == expected);
+        assert_eq!(db.cached_projections(), 4);
+    }
+
+    db.set_projection_cache(0);
+    assert_eq!(db.cached_projections(), 0);
+}
+
+#[test]
+fn test_replay_into() {
+    let mut db = InMemDb::default();
+
+    for (subject, event_type) in [
+        ("companies/krispy", "company-created"),
+        ("users/sarah", "user-created"),
+        ("companies/krispy/employees", "employee-hired"),
+        ("companies/acme", "company-created"),
+    ] {
+        db.append(
+            subject,
+            vec![Event {
+                event_type: event_type.to_string(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+
+    let mut seen = vec![];
+    db.replay_into(None, |event| seen.push(event.event_type.clone()));
+    assert_eq!(
+        seen,
+        vec![
+            "company-created",
+            "user-created",
+            "employee-hired",
+            "company-created"
+        ]
+    );
+
+    let mut seen = vec![];
+    db.replay_into(Some("companies"), |event| {
+        seen.push(event.event_type.clone())
+    });
+    assert_eq!(
+        seen,
+        vec!["company-created", "employee-hired", "company-created"]
+    );
+}
+
+#[test]
+fn test_run_query_filtered_by_event_type() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let scanned = db
+        .run_query(include_str!("./resources/query_filtered_by_event_type.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(scanned.len(), db.iter_types("user-created").count());
+    insta::assert_yaml_snapshot!(scanned);
+}
+
+#[test]
+fn test_run_query_event_type_pushdown_scans_less() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    // the salary check keeps the predicate from narrowing the scan without changing its outcome
+    let full_scan = include_str!("./resources/query_filtered_by_event_type_full_scan.eql");
+    let expected = db
+        .run_query(full_scan)
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    // every row pulled from a source counts against the budget
+    db.query_options_mut().row_budget = Some(db.iter_types("user-created").count() as u64);
+
+    let narrowed = db
+        .run_query(include_str!("./resources/query_filtered_by_event_type.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    assert!(narrowed == expected);
+    assert!(matches!(
+        db.run_query(full_scan)
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>(),
+        Err(EvalError::RowBudgetExceeded)
+    ));
+}
+
+#[test]
+fn test_run_query_department_grouping() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let mut result = db
+        .run_query(include_str!("./resources/department-grouping.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    result.sort_by_key(|v| {
+        if let QueryValue::Record(props) = v {
+            props
+                .get("department")
+                .unwrap()
+                .as_str_or_panic()
+                .to_string()
+        } else {
+            "const".to_string()
+        }
+    });
+
+    insta::assert_yaml_snapshot!(result);
+}
+
+#[test]
+fn test_run_query_department_grouping_ordered() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/department-grouping-ordered.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_run_query_department_grouping_ordered_by_alias() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/department-grouping-ordered-by-alias.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_run_query_department_grouping_ordered_by_key() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/department-grouping-ordered-by-key.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_prepared_query_runs_many_times() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let prepared = db
+        .prepare(include_str!("./resources/query_prepared.eql"))
+        .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        "prepared_query_first_run",
+        prepared.run(&db).collect::<EvalResult<Vec<_>>>()
+    );
+
+    db.append(
+        "krispy/boston/employees/nina_park",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            datacontenttype: "application/json".to_string(),
+            data: serde_json::to_vec(&serde_json::json!({
+                "department": "sales",
+                "salary": 80000,
+            }))
+            .unwrap(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        "prepared_query_second_run",
+        prepared.run(&db).collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_explain_grouping_query() {
+    let db = InMemDb::default();
+
+    insta::assert_yaml_snapshot!(
+        db.explain(include_str!(
+            "./resources/department-grouping-having-alias.eql"
+        ))
+        .unwrap()
+    );
+}
+
+#[test]
+fn test_query_stats() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    for (query, expected) in [
+        (
+            include_str!("./resources/query_whole_event.eql"),
+            QueryStats {
+                rows_scanned: 6,
+                rows_emitted: 6,
+                groups: 0,
+            },
+        ),
+        (
+            include_str!("./resources/department-grouping.eql"),
+            QueryStats {
+                rows_scanned: 6,
+                rows_emitted: 3,
+                groups: 3,
+            },
+        ),
+    ] {
+        let mut rows = db.run_query(query).unwrap();
+
+        assert!(rows.by_ref().all(|row| row.is_ok()));
+        assert_eq!(rows.stats(), Some(expected));
+    }
+}
+
+#[test]
+fn test_query_count_serializes_as_integer() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let result = db
+        .run_query(include_str!("./resources/department-grouping-ordered.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>();
+
+    insta::assert_snapshot!(serde_json::to_string_pretty(&result).unwrap());
+}
+
+#[test]
+fn test_prepared_queries_run_through_shared_db() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let grouped = db
+        .prepare(include_str!("./resources/department-grouping-ordered.eql"))
+        .unwrap();
+    let events = db
+        .prepare(include_str!("./resources/query_whole_event.eql"))
+        .unwrap();
+
+    let db = &db;
+    let mut first = grouped.run(db);
+    let second = events.run(db);
+
+    // both processors borrow the same database while interleaving
+    let mut rows = 0;
+    for row in second {
+        assert!(row.is_ok());
+        assert!(first.next().is_none_or(|row| row.is_ok()));
+        rows += 1;
+    }
+
+    assert_eq!(rows, 6);
+    assert!(first.next().is_none());
+}
+
+#[test]
+fn test_queries_run_alongside_appends() {
+    let db = Arc::new(RwLock::new(InMemDb::default()));
+
+    load_departments_dataset(&mut db.write().unwrap());
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..50 {
+                db.write()
+                    .unwrap()
+                    .append(
+                        "krispy/boston/employees/nina_park",
+                        vec![Event {
+                            event_type: "user-updated".to_string(),
+                            datacontenttype: "application/json".to_string(),
+                            data: serde_json::to_vec(&serde_json::json!({ "salary": i })).unwrap(),
+                            ..Default::default()
+                        }],
+                    )
+                    .unwrap();
+            }
+        });
+
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let mut seen = 0;
+
+                for _ in 0..20 {
+                    let db = db.read().unwrap();
+                    let rows = db
+                        .run_query(include_str!("./resources/query_whole_event.eql"))
+                        .unwrap()
+                        .collect::<EvalResult<Vec<_>>>()
+                        .unwrap();
+
+                    // appends land between queries, never in the middle of one
+                    assert!((6..=56).contains(&rows.len()));
+                    assert!(rows.len() >= seen);
+                    seen = rows.len();
+                }
+            });
+        }
+    });
+
+    let db = db.read().unwrap();
+    assert_eq!(
+        db.run_query(include_str!("./resources/query_whole_event.eql"))
+            .unwrap()
+            .count(),
+        56
+    );
+}
+
+#[test]
+fn test_query_rows_deserialize_into_structs() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    struct DepartmentSummary {
+        department: String,
+        employee_count: u32,
+        avg_salary: f64,
+    }
+
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let summaries = db
+        .run_query(include_str!("./resources/department-grouping-ordered.eql"))
+        .unwrap()
+        .map(|row| row?.deserialize_into::<DepartmentSummary>())
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(
+        summaries.last(),
+        Some(&DepartmentSummary {
+            department: "marketing".to_string(),
+            employee_count: 1,
+            avg_salary: 70000.0,
+        })
+    );
+    assert_eq!(summaries.len(), 3);
+}
+
+#[test]
+fn test_query_into_json_writer() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let mut output = Vec::new();
+    db.run_query(include_str!("./resources/department-grouping-ordered.eql"))
+        .unwrap()
+        .into_json_writer(&mut output)
+        .unwrap();
+
+    insta::assert_snapshot!(String::from_utf8(output).unwrap());
+}
+
+#[test]
+fn test_query_into_csv_writer() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let mut output = Vec::new();
+    db.run_query(include_str!("./resources/department-grouping-ordered.eql"))
+        .unwrap()
+        .into_csv_writer(&mut output, b',')
+        .unwrap();
+
+    insta::assert_snapshot!(String::from_utf8(output).unwrap());
+}
+
+#[test]
+fn test_run_query_department_grouping_having() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/department-grouping-having.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_run_query_department_grouping_having_alias() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/department-grouping-having-alias.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_order_by() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_order_by.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_order_by_desc() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_order_by_desc.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_order_by_composite() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    // department first, salary breaks the ties
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_order_by_composite.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_run_query_department_grouping_ordered_composite() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    // engineering and sales both have several employees, the average salary breaks the tie
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/department-grouping-ordered-composite.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_event_types() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_event_types.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_subjects() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_subjects.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_subject_counts() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+    db.append(
+        "krispy/nyc/employees/sarah_coleman",
+        vec![Event {
+            event_type: "user-updated".to_string(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_subject_counts.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_order_by_ingestion() {
+    let mut db = InMemDb::default();
+    let at = |s: &str| s.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+
+    db.append(
+        "companies/krispy",
+        vec![
+            Event::builder()
+                .event_type("user-updated")
+                .ingested_at(at("2025-03-01T10:00:00Z"))
+                .build(),
+            Event::builder()
+                .event_type("user-created")
+                .ingested_at(at("2025-01-01T10:00:00Z"))
+                .build(),
+        ],
+    )
+    .unwrap();
+
+    let offsets = db
+        .append_returning(
+            "companies/krispy",
+            vec![Event::builder().event_type("user-deleted").build()],
+        )
+        .unwrap();
+
+    // left unset, the ingestion time is the append time
+    assert!(db.get(offsets[0]).unwrap().ingested_at > at("2025-03-01T10:00:00Z"));
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_order_by_ingestion.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_agg_distinct() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_agg_distinct.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_distinct() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_distinct.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_distinct_records() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_distinct_records.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_agg_functions() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let mut result = db
+        .run_query(include_str!("./resources/query_agg_functions.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    result.sort_by_key(|v| {
+        if let QueryValue::Record(props) = v {
+            props
+                .get("department")
+                .unwrap()
+                .as_str_or_panic()
+                .to_string()
+        } else {
+            "const".to_string()
+        }
+    });
+
+    insta::assert_yaml_snapshot!(result);
+}
+
+#[test]
+fn test_query_parallel_aggregates_match_serial() {
+    let mut db = InMemDb::default();
+
+    // enough rows to get several partitions
+    for _ in 0..4 {
+        load_departments_dataset(&mut db);
+    }
+
+    for query in [
+        include_str!("./resources/department-grouping-ordered.eql"),
+        include_str!("./resources/query_array_agg.eql"),
+    ] {
+        db.query_options_mut().parallel_aggregates = false;
+        let serial = db
+            .run_query(query)
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+            .unwrap();
+
+        db.query_options_mut().parallel_aggregates = true;
+        let parallel = db
+            .run_query(query)
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+            .unwrap();
+
+        assert!(!serial.is_empty());
+        assert!(serial == parallel);
+    }
+}
+
+#[test]
+fn test_query_unique_strict() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    // every department but marketing has several employees, hence several first names
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_unique_strict.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_agg_unique_strict() {
+    let fold = |values: &[&str]| {
+        let mut agg = Agg::unique_strict();
+        for value in values {
+            agg.fold(&[QueryValue::String(value.to_string())]);
+        }
+
+        agg
+    };
+
+    assert!(fold(&["sales", "sales"]).complete().unwrap() == QueryValue::String("sales".into()));
+    assert!(fold(&["sales", "marketing"]).complete().is_err());
+
+    let mut merged = fold(&["sales"]);
+    merged.merge(&fold(&["sales"])).unwrap();
+    assert!(merged.complete().is_ok());
+
+    merged.merge(&fold(&["marketing"])).unwrap();
+    assert!(merged.complete().is_err());
+}
+
+#[test]
+fn test_query_histogram() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_histogram.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+
+    assert!(
+        db.run_query(include_str!("./resources/query_histogram_zero_width.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+            .is_err()
+    );
+}
+
+#[test]
+fn test_agg_histogram_negative_values() {
+    let mut agg = Agg::histogram(10.0);
+    for value in [-15.0, -10.0, -0.5, 0.0, 9.99, 10.0, 25.0] {
+        agg.fold(&[
+            QueryValue::Number(10.0.into()),
+            QueryValue::Number(value.into()),
+        ]);
+    }
+
+    agg.fold(&[QueryValue::Number(10.0.into()), QueryValue::Null]);
+
+    let QueryValue::Record(buckets) = agg.complete().unwrap() else {
+        panic!("expected a record");
+    };
+
+    assert_eq!(buckets.iter().count(), 5);
+
+    for (bound, count) in [
+        ("-20", 1.0),
+        ("-10", 2.0),
+        ("0", 2.0),
+        ("10", 1.0),
+        ("20", 1.0),
+    ] {
+        assert!(buckets[bound] == QueryValue::Number(count.into()));
+    }
+}
+
+#[test]
+fn test_agg_histogram_fractional_width() {
+    let mut agg = Agg::histogram(0.1);
+    for value in [-0.05, 0.05, 0.25, 0.31, 0.35, 1.25] {
+        agg.fold(&[
+            QueryValue::Number(0.1.into()),
+            QueryValue::Number(value.into()),
+        ]);
+    }
+
+    let QueryValue::Record(buckets) = agg.complete().unwrap() else {
+        panic!("expected a record");
+    };
+
+    assert_eq!(
+        buckets
+            .iter()
+            .map(|(bound, _)| bound.as_str())
+            .collect::<Vec<_>>(),
+        ["-0.1", "0", "0.2", "0.3", "1.2"]
+    );
+    assert!(buckets["0.3"] == QueryValue::Number(2.0.into()));
+}
+
+#[test]
+fn test_agg_covar_corr() {
+    let xs = [1.0, 2.0, 3.0, 4.0];
+    let ys = [2.0, 4.0, 5.0, 9.0];
+    let fold = |mut agg: Agg, len: usize| {
+        for (x, y) in xs.iter().zip(&ys).take(len) {
+            agg.fold(&[
+                QueryValue::Number((*x).into()),
+                QueryValue::Number((*y).into()),
+            ]);
+        }
+
+        agg.fold(&[QueryValue::Number(5.0.into()), QueryValue::Null]);
+        agg.complete().unwrap()
+    };
+    let number = |value: QueryValue| match value {
+        QueryValue::Number(n) => *n,
+        _ => panic!("expected a number"),
+    };
+
+    // n = 4, Σx = 10, Σy = 20, Σxy = 61, Σx² = 30, Σy² = 126
+    assert!((number(fold(Agg::covar(), 4)) - 2.75).abs() < 1e-9);
+    assert!((number(fold(Agg::corr(), 4)) - 44.0 / 2080f64.sqrt()).abs() < 1e-9);
+
+    assert!(fold(Agg::covar(), 1) == QueryValue::Null);
+    assert!(fold(Agg::corr(), 1) == QueryValue::Null);
+}
+
+#[test]
+fn test_query_stddev_variance_pop_samp() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/query_stddev_variance_pop_samp.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_agg_variance_pop_samp() {
+    let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+    let fold = |mut agg: Agg, values: &[f64]| {
+        for value in values {
+            agg.fold(&[QueryValue::Number((*value).into())]);
+        }
+
+        agg
+    };
+    let number = |value: QueryValue| match value {
+        QueryValue::Number(n) => *n,
+        _ => panic!("expected a number"),
+    };
+
+    // mean 5, squared distances summing to 32
+    let pop = number(fold(Agg::var_pop(), &values).complete().unwrap());
+    let samp = number(fold(Agg::var_samp(), &values).complete().unwrap());
+    assert!((pop - 4.0).abs() < 1e-9);
+    assert!((samp - 32.0 / 7.0).abs() < 1e-9);
+
+    let pop = number(fold(Agg::stddev(), &values).complete().unwrap());
+    let samp = number(fold(Agg::stddev_samp(), &values).complete().unwrap());
+    assert!((pop - 2.0).abs() < 1e-9);
+    assert!((samp - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+
+    // a single value has no sample variance
+    assert!(fold(Agg::var_samp(), &[3.0]).complete().unwrap() == QueryValue::Null);
+    assert!(fold(Agg::var_pop(), &[3.0]).complete().unwrap() == QueryValue::Number(0.0.into()));
+
+    assert!(Agg::var_pop().merge(&Agg::var_samp()).is_err());
+}
+
+#[test]
+fn test_agg_sum_renders_integers() {
+    let sum = |values: &[f64]| {
+        let mut agg = Agg::sum();
+        for value in values {
+            agg.fold(&[QueryValue::Number((*value).into())]);
+        }
+
+        serde_json::to_string(&agg.complete().unwrap().plain_json()).unwrap()
+    };
+
+    assert_eq!(sum(&[1.0, 2.0, 3.0]), "6");
+    assert_eq!(sum(&[-4.0, 4.0]), "0");
+    assert_eq!(sum(&[1.5, 2.0]), "3.5");
+    assert_eq!(sum(&[]), "0");
+}
+
+#[test]
+fn test_number_rendering_edges() {
+    let render =
+        |n: f64| serde_json::to_string(&QueryValue::Number(n.into()).plain_json()).unwrap();
+
+    assert_eq!(render(0.0), "0");
+    assert_eq!(render(-0.0), "-0.0");
+    assert_eq!(render(-(2f64.powi(53))), "-9007199254740992");
+    // 2^63 is past i64::MAX, casting it would saturate
+    assert_eq!(render(2f64.powi(63)), "9.223372036854776e+18");
+    assert_eq!(render(-(2f64.powi(63))), "-9.223372036854776e+18");
+}
+
+#[test]
+fn test_agg_merge_stddev() {
+    let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0, 11.5, 3.25];
+    let fold = |values: &[f64]| {
+        let mut agg = Agg::stddev();
+        for value in values {
+            agg.fold(&[QueryValue::Number((*value).into())]);
+        }
+
+        agg
+    };
+    let number = |value: QueryValue| match value {
+        QueryValue::Number(n) => *n,
+        _ => panic!("expected a number"),
+    };
+
+    let expected = number(fold(&values).complete().unwrap());
+
+    for at in [0, 1, 4, values.len()] {
+        let (lhs, rhs) = values.split_at(at);
+        let mut merged = fold(lhs);
+        merged.merge(&fold(rhs)).unwrap();
+
+        assert!((number(merged.complete().unwrap()) - expected).abs() < 1e-9);
+    }
+
+    assert!(Agg::stddev().merge(&Agg::variance()).is_err());
+}
+
+#[test]
+fn test_query_top() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_top.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_skip() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_skip.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_agg_top() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_agg_top.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_agg_skip() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_agg_skip.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_simple_sub_query() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/simple-sub-query.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_url_html_encoding() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_url_html_encoding.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_add_sub() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-31T23:30:00Z"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_add_sub.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_add_sub_on_dates() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-31"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_add_sub_date.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_add_rejects_fractional_amount() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-31T23:30:00Z"]);
+
+    let Err(EvalError::Runtime(e)) = db
+        .run_query(include_str!("./resources/query_date_add_fractional.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    else {
+        panic!("a fractional amount should fail the query");
+    };
+
+    assert_eq!(
+        e,
+        "date_add() and date_sub() require an integer amount, got 1.5"
+    );
+}
+
+#[test]
+fn test_query_hash_functions() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_hash_functions.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_diff() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "tickets/1",
+        vec![Event {
+            event_type: "ticket-closed".to_string(),
+            datacontenttype: "application/json".to_string(),
+            data: serde_json::to_vec(&serde_json::json!({
+                "openedAt": "2024-02-28T00:00:00Z",
+                "closedAt": "2024-03-01T12:00:00Z",
+            }))
+            .unwrap(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_diff.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_diff_on_dates() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-03-01"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_diff_dates.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_diff_rejects_mismatched_kinds() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-03-01T12:00:00Z"]);
+
+    let Err(EvalError::Runtime(e)) = db
+        .run_query(include_str!("./resources/query_date_diff_mismatched.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    else {
+        panic!("a DateTime against a Date should fail the query");
+    };
+
+    assert_eq!(e, "date_diff() requires two DateTime or two Date arguments");
+}
+
+#[test]
+fn test_query_normalized_group_keys() {
+    let mut db = InMemDb::default();
+
+    for department in ["Sales ", "sales", "marketing", " SALES"] {
+        db.append(
+            "krispy/employees",
+            vec![Event {
+                event_type: "user-created".to_string(),
+                datacontenttype: "application/json".to_string(),
+                data: serde_json::to_vec(&serde_json::json!({ "department": department })).unwrap(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+
+    db.query_options_mut().normalize_group_keys = true;
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_normalized_group_keys.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_trunc_grouping() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(
+        &mut db,
+        &[
+            "2024-01-01T08:00:00Z",
+            "2024-01-01T17:45:00Z",
+            "2024-01-02T09:30:00Z",
+        ],
+    );
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_trunc_grouping.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_date_trunc_on_dates() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-02-29"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_date_trunc_date.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_array_agg() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_array_agg.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_json_object_agg() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_json_object_agg.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_now_is_stable() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-01T08:00:00Z"]);
+
+    // the subquery and the outer query agree on the instant the query started at
+    for row in db
+        .run_query(include_str!("./resources/query_now_is_stable.eql"))
+        .unwrap()
+    {
+        let QueryValue::Record(row) = row.unwrap() else {
+            panic!("expected a record");
+        };
+
+        assert!(row.get("same") == Some(&QueryValue::Bool(true)));
+    }
+
+    db.query_options_mut().now = Some("2024-06-01T12:00:00Z".parse().unwrap());
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_now_is_stable.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_array_functions() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-01T08:00:00Z"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_array_functions.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_array_contains_and_distinct() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-01T08:00:00Z"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!(
+            "./resources/query_array_contains_distinct.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_sort_array() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-01T08:00:00Z"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_sort_array.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_gap_fill_hourly() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(
+        &mut db,
+        &[
+            "2024-01-01T08:15:00Z",
+            "2024-01-01T08:40:00Z",
+            "2024-01-01T10:05:00Z",
+        ],
+    );
+
+    db.query_options_mut().gap_fill = Some(GapFill {
+        step: chrono::TimeDelta::hours(1),
+        range: None,
+    });
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_gap_fill_hourly.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_gap_fill_seeds_grouping_unique_only() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(
+        &mut db,
+        &[
+            "2024-01-01T08:15:00Z",
+            "2024-01-01T08:40:00Z",
+            "2024-01-01T10:05:00Z",
+        ],
+    );
+
+    db.query_options_mut().gap_fill = Some(GapFill {
+        step: chrono::TimeDelta::hours(1),
+        range: None,
+    });
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_gap_fill_unique.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_gap_fill_respects_max_groups() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-01-01T08:15:00Z", "2024-01-01T10:05:00Z"]);
+
+    db.query_options_mut().max_groups = Some(2);
+    db.query_options_mut().gap_fill = Some(GapFill {
+        step: chrono::TimeDelta::hours(1),
+        range: None,
+    });
+
+    // two buckets hold events, filling the gap between them makes a third group
+    let Err(EvalError::Runtime(e)) = db
+        .run_query(include_str!("./resources/query_gap_fill_hourly.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    else {
+        panic!("gap filling should exceed the limit of 2 groups");
+    };
+
+    assert_eq!(e, "query exceeds the limit of 2 distinct groups");
+}
+
+#[test]
+fn test_query_parse_date() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_parse_date.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_typeof() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_typeof.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_to_number_bool() {
+    let mut db = InMemDb::default();
+
+    for payload in [
+        serde_json::json!({ "amount": "42.5", "active": "true" }),
+        serde_json::json!({ "amount": " 7 ", "active": "0" }),
+        serde_json::json!({ "amount": "abc", "active": "yes" }),
+        serde_json::json!({ "amount": "NaN", "active": 1 }),
+    ] {
+        db.append(
+            "orders",
+            vec![
+                Event::builder()
+                    .event_type("order-placed")
+                    .json_data(&payload)
+                    .unwrap()
+                    .build(),
+            ],
+        )
+        .unwrap();
+    }
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_to_number_bool.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_string_coercions() {
+    let mut db = InMemDb::default();
+
+    db.append(
+        "orders",
+        vec![
+            Event::builder()
+                .event_type("order-placed")
+                .json_data(&serde_json::json!({
+                    "amount": "42.5",
+                    "day": "2025-03-01",
+                    "at": "2025-03-01T10:30:00Z",
+                }))
+                .unwrap()
+                .build(),
+        ],
+    )
+    .unwrap();
+
+    for (name, query) in [
+        (
+            "query_string_coercions",
+            include_str!("./resources/query_string_coercions.eql"),
+        ),
+        (
+            "query_string_coercion_invalid",
+            include_str!("./resources/query_string_coercion_invalid.eql"),
+        ),
+    ] {
+        insta::assert_yaml_snapshot!(
+            name,
+            db.run_query(query).unwrap().collect::<EvalResult<Vec<_>>>()
+        );
+    }
+}
+
+#[test]
+fn test_query_is_null() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_is_null.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_format_date() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_format_date.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_format_date_payload_field() {
+    let mut db = InMemDb::default();
+
+    load_timestamps_dataset(&mut db, &["2024-02-29T13:45:00Z"]);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_format_date_payload.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_count_if() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_count_if.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_order_by_nulls_placement() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    for (name, query, nulls) in [
+        (
+            "query_order_by_asc_nulls_first",
+            include_str!("./resources/query_order_by_nulls_asc.eql"),
+            NullsPlacement::First,
+        ),
+        (
+            "query_order_by_asc_nulls_last",
+            include_str!("./resources/query_order_by_nulls_asc.eql"),
+            NullsPlacement::Last,
+        ),
+        (
+            "query_order_by_desc_nulls_first",
+            include_str!("./resources/query_order_by_nulls_desc.eql"),
+            NullsPlacement::First,
+        ),
+        (
+            "query_order_by_desc_nulls_last",
+            include_str!("./resources/query_order_by_nulls_desc.eql"),
+            NullsPlacement::Last,
+        ),
+    ] {
+        db.query_options_mut().nulls = nulls;
+
+        insta::assert_yaml_snapshot!(
+            name,
+            db.run_query(query).unwrap().collect::<EvalResult<Vec<_>>>()
+        );
+    }
+}
+
+#[test]
+fn test_query_order_by_rand_seeded() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+    db.query_options_mut().seed = Some(42);
+
+    let query = include_str!("./resources/query_order_by_rand.eql");
+    let first = db
+        .run_query(query)
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+    let second = db
+        .run_query(query)
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    assert!(first == second);
+    insta::assert_yaml_snapshot!(first);
+}
+
+#[test]
+fn test_orderer_bounded_matches_unbounded() {
+    // plenty of ties and some nulls, in no particular order
+    let rows = (0..1000u32)
+        .map(|i| {
+            let key = match i * 7919 % 101 {
+                k if k % 13 == 0 => QueryValue::Null,
+                k => QueryValue::Number(f64::from(k % 37).into()),
+            };
+
+            (key, QueryValue::Number(f64::from(i).into()))
+        })
+        .collect::<Vec<_>>();
+
+    for order in [Order::Asc, Order::Desc] {
+        for nulls in [
+            NullsPlacement::Natural,
+            NullsPlacement::First,
+            NullsPlacement::Last,
+        ] {
+            let mut full = QueryOrderer::new(order, nulls);
+            let mut top = QueryOrderer::new(order, nulls).bounded(10);
+
+            for (key, value) in &rows {
+                full.insert(key.clone(), value.clone());
+                top.insert(key.clone(), value.clone());
+
+                assert!(top.buffered() <= 10);
+            }
+
+            assert_eq!(full.buffered(), rows.len());
+
+            full.prepare_for_streaming().unwrap();
+            top.prepare_for_streaming().unwrap();
+
+            let expected = std::iter::from_fn(|| full.next())
+                .take(10)
+                .collect::<Vec<_>>();
+            let actual = std::iter::from_fn(|| top.next()).collect::<Vec<_>>();
+
+            assert!(actual == expected);
+        }
+    }
+}
+
+#[test]
+fn test_orderer_ties_keep_insertion_order() {
+    let rows = [(2.0, "a"), (1.0, "b"), (2.0, "c"), (1.0, "d"), (2.0, "e")];
+
+    let ordered = |order: Order| {
+        let mut orderer = QueryOrderer::new(order, NullsPlacement::Natural);
+
+        for (key, name) in rows {
+            orderer.insert(
+                QueryValue::Number(key.into()),
+                QueryValue::String(name.to_string()),
+            );
+        }
+
+        orderer.prepare_for_streaming().unwrap();
+
+        let mut names = vec![];
+        while let Some(QueryValue::String(name)) = orderer.next() {
+            names.push(name);
+        }
+
+        names
+    };
+
+    assert_eq!(ordered(Order::Asc), ["b", "d", "a", "c", "e"]);
+    assert_eq!(ordered(Order::Desc), ["a", "c", "e", "b", "d"]);
+}
+
+#[test]
+fn test_orderer_multi_key_mixed_directions() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let employees = db
+        .run_query("FROM e IN events PROJECT INTO e.data")
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+        .unwrap();
+
+    let ordered_names = |directions: Vec<Order>| {
+        let mut orderer = QueryOrderer::multi(directions, NullsPlacement::Natural);
+
+        for employee in &employees {
+            let QueryValue::Record(fields) = employee else {
+                panic!("expected a record");
+            };
+
+            orderer.insert_keys(
+                vec![fields["department"].clone(), fields["salary"].clone()],
+                fields["firstName"].clone(),
+            );
+        }
+
+        orderer.prepare_for_streaming().unwrap();
+
+        let mut names = vec![];
+        while let Some(QueryValue::String(name)) = orderer.next() {
+            names.push(name);
+        }
+
+        names
+    };
+
+    assert_eq!(
+        ordered_names(vec![Order::Asc, Order::Desc]),
+        ["Sarah", "James", "Derek", "Laura", "Marcus", "Emily"]
+    );
+
+    assert_eq!(
+        ordered_names(vec![Order::Desc, Order::Asc]),
+        ["Emily", "Marcus", "Laura", "Derek", "James", "Sarah"]
+    );
+}
+
+#[test]
+fn test_estimated_cardinality() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    assert_eq!(
+        db.estimated_cardinality(DataSource::Types(&["user-created"])),
+        Some(4)
+    );
+    assert_eq!(
+        db.estimated_cardinality(DataSource::Types(&["user-created", "user-updated"])),
+        Some(6)
+    );
+    assert_eq!(
+        db.estimated_cardinality(DataSource::Types(&["user-deleted"])),
+        Some(0)
+    );
+    assert_eq!(
+        db.estimated_cardinality(DataSource::Subject("krispy/boston")),
+        Some(db.iter_subject_events("krispy/boston").count())
+    );
+    assert_eq!(
+        db.estimated_cardinality(DataSource::Named("events")),
+        Some(6)
+    );
+}
+
+#[test]
+fn test_append_returning_offsets() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let offsets = db
+        .append_returning(
+            "krispy/nyc/employees/sarah_coleman",
+            vec![
+                Event {
+                    event_type: "user-promoted".to_string(),
+                    ..Default::default()
+                },
+                Event {
+                    event_type: "user-relocated".to_string(),
+                    ..Default::default()
+                },
+            ],
+        )
+        .unwrap();
+
+    assert_eq!(offsets, [6, 7]);
+    assert_eq!(db.get(offsets[0]).unwrap().event_type, "user-promoted");
+    assert_eq!(db.get(offsets[1]).unwrap().event_type, "user-relocated");
+    assert!(db.get(8).is_none());
+}
+
+#[test]
+fn test_query_record_declared_field_order() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_record_field_order.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+
+    let declared = Record::from_iter([
+        ("z".to_string(), QueryValue::Number(1f64.into())),
+        ("a".to_string(), QueryValue::Number(2f64.into())),
+    ]);
+    let sorted = Record::from_iter([
+        ("a".to_string(), QueryValue::Number(2f64.into())),
+        ("z".to_string(), QueryValue::Number(1f64.into())),
+    ]);
+
+    assert!(QueryValue::Record(declared) == QueryValue::Record(sorted));
+}
+
+#[test]
+fn test_record_ordering_ignores_field_order() {
+    use std::hash::{BuildHasher, RandomState};
+
+    let number = |n: f64| QueryValue::Number(n.into());
+    let declared = Record::from_iter([
+        ("z".to_string(), number(1.0)),
+        ("m".to_string(), number(3.0)),
+        ("a".to_string(), number(2.0)),
+    ]);
+    let mut sorted = Record::new();
+    sorted.insert("a".to_string(), number(0.0));
+    sorted.insert("m".to_string(), number(3.0));
+    sorted.insert("z".to_string(), number(1.0));
+
+    // replacing a value keeps the field where it was
+    assert!(sorted.insert("a".to_string(), number(2.0)) == Some(number(0.0)));
+    assert_eq!(sorted.iter().next().unwrap().0, "a");
+
+    let hasher = RandomState::new();
+    assert_eq!(declared.cmp(&sorted), Ordering::Equal);
+    assert_eq!(hasher.hash_one(&declared), hasher.hash_one(&sorted));
+
+    // records compare field by field in name order, `a` decides before `z`
+    let greater = Record::from_iter([
+        ("z".to_string(), number(0.0)),
+        ("a".to_string(), number(5.0)),
+    ]);
+    assert_eq!(declared.cmp(&greater), Ordering::Less);
+    assert_eq!(greater.cmp(&declared), Ordering::Greater);
+}
+
+#[test]
+fn test_query_csv_payload_columns() {
+    let mut db = InMemDb::default();
+
+    load_csv_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_csv_payload_columns.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_csv_payload_rows() {
+    let mut db = InMemDb::default();
+
+    load_csv_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_csv_payload_rows.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_inner_join() {
     let mut db = InMemDb::default();
-    insta::assert_yaml_snapshot!(db.append(
-        "/path/to/file",
-        vec![Event {
-            event_type: "user-created".to_string(),
-            ..Default::default()
-        }]
-    ));
+
+    load_departments_dataset(&mut db);
+
+    load_bonuses_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_inner_join.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
 }
 
 #[test]
-fn test_run_query_from_events() {
+fn test_query_cross_join() {
     let mut db = InMemDb::default();
 
-    db.append(
-        "companies/krispy",
-        vec![Event {
-            event_type: "user-created".to_string(),
-            id: uuid!("1e7b9531-1392-48fe-aaf1-94d4cae74a9d"),
-            ..Default::default()
-        }],
-    )
-    .unwrap();
+    load_departments_dataset(&mut db);
 
-    db.append(
-        "companies/krispy",
-        vec![Event {
-            event_type: "user-deleted".to_string(),
-            id: uuid!("77344193-67bb-44af-a854-77d5d56dbb3d"),
-            ..Default::default()
-        }],
-    )
-    .unwrap();
+    load_bonuses_dataset(&mut db);
 
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_from_events.eql"))
+        db.run_query(include_str!("./resources/query_cross_join.eql"))
             .unwrap()
-            .collect::<Vec<_>>()
+            .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_run_query_department_grouping() {
+fn test_limited_iterators() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
 
-    let mut result = db
-        .run_query(include_str!("./resources/department-grouping.eql"))
-        .unwrap()
-        .collect::<EvalResult<Vec<_>>>()
-        .unwrap();
-
-    result.sort_by_key(|v| {
-        if let QueryValue::Record(props) = v {
-            props
-                .get("department")
-                .unwrap()
-                .as_str_or_panic()
-                .to_string()
-        } else {
-            "const".to_string()
-        }
-    });
+    assert_eq!(db.iter_types("user-created").count(), 4);
+    assert_eq!(db.iter_types_limited("user-created", 2).count(), 2);
+    assert_eq!(db.iter_types_limited("user-created", 10).count(), 4);
+    assert_eq!(db.iter_types_limited("user-created", 0).count(), 0);
 
-    insta::assert_yaml_snapshot!(result);
+    assert_eq!(db.iter_subject_events("krispy").count(), 6);
+    assert_eq!(db.iter_subject_events_limited("krispy", 3).count(), 3);
+    assert_eq!(
+        db.iter_subject_events_limited("krispy/boston", 5).count(),
+        2
+    );
 }
 
 #[test]
-fn test_run_query_department_grouping_ordered() {
+fn test_query_multiple_sources_without_join_predicate() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
+    load_bonuses_dataset(&mut db);
 
+    // every bonus gets paired with every employee instead of stopping at the shortest source
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/department-grouping-ordered.eql"))
+        db.run_query(include_str!("./resources/query_cross_sources.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
+
 #[test]
-fn test_run_query_department_grouping_having() {
+fn test_query_row_budget_exceeded() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
+    db.query_options_mut().row_budget = Some(20);
 
+    // 6 rows pulled by the subquery, then 36 combinations once joined with the events
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/department-grouping-having.eql"))
+        db.run_query(include_str!("./resources/query_row_budget.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_order_by() {
+fn test_query_max_groups_exceeded() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
+    db.query_options_mut().max_groups = Some(4);
 
+    // every employee has its own id, so 6 groups
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_order_by.eql"))
+        db.run_query(include_str!("./resources/query_max_groups.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_order_by_desc() {
+fn test_query_max_groups_exceeded_in_parallel() {
+    let mut db = InMemDb::default();
+
+    for _ in 0..4 {
+        load_departments_dataset(&mut db);
+    }
+
+    db.query_options_mut().max_groups = Some(4);
+    db.query_options_mut().parallel_aggregates = true;
+
+    let Err(EvalError::Runtime(e)) = db
+        .run_query(include_str!("./resources/query_max_groups.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    else {
+        panic!("6 distinct ids should exceed the limit of 4 groups");
+    };
+
+    assert_eq!(e, "query exceeds the limit of 4 distinct groups");
+}
+
+#[test]
+fn test_query_invalid_function_args() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    let Err(e) = db
+        .run_query(include_str!("./resources/query_invalid_function_args.eql"))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    else {
+        panic!("UPPER() on a number should fail");
+    };
+
+    let message = e.to_string();
+    assert!(message.contains("UPPER"));
+    assert!(message.contains("(number)"));
+}
+
+#[test]
+fn test_query_missing_function_args() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    for (query, expected) in [
+        (
+            include_str!("./resources/query_substring_missing_args.eql"),
+            "substring expects 3 arguments",
+        ),
+        (
+            include_str!("./resources/query_pow_missing_args.eql"),
+            "pow expects 2 arguments",
+        ),
+    ] {
+        let Err(EvalError::Runtime(e)) =
+            db.run_query(query).unwrap().collect::<EvalResult<Vec<_>>>()
+        else {
+            panic!("missing arguments should fail the query");
+        };
+
+        assert_eq!(e, expected);
+    }
+}
+
+#[test]
+fn test_query_row_budget_single_source() {
+    let mut db = InMemDb::default();
+
+    load_departments_dataset(&mut db);
+
+    for query in [
+        include_str!("./resources/query_order_by.eql"),
+        include_str!("./resources/department-grouping-ordered.eql"),
+    ] {
+        // the 6 events of the dataset fit exactly
+        db.query_options_mut().row_budget = Some(6);
+        assert!(
+            db.run_query(query)
+                .unwrap()
+                .collect::<EvalResult<Vec<_>>>()
+                .is_ok()
+        );
+
+        db.query_options_mut().row_budget = Some(3);
+        assert!(matches!(
+            db.run_query(query).unwrap().collect::<EvalResult<Vec<_>>>(),
+            Err(EvalError::RowBudgetExceeded)
+        ));
+    }
+}
+
+#[test]
+fn test_query_cancelled_mid_iteration() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
 
+    let mut rows = db
+        .run_query(include_str!("./resources/query_filtered_by_event_type.eql"))
+        .unwrap();
+    let handle = rows.cancellation_handle().unwrap();
+
+    assert!(rows.next().unwrap().is_ok());
+    assert!(rows.next().unwrap().is_ok());
+
+    handle.cancel();
+
+    assert!(matches!(rows.next(), Some(Err(EvalError::Cancelled))));
+    assert!(rows.next().is_none());
+}
+
+#[test]
+fn test_query_null_comparisons() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_order_by_desc.eql"))
+        db.run_query(include_str!("./resources/query_null_comparisons.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_event_types() {
+fn test_query_null_comparison_predicate() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    load_payroll_dataset(&mut db);
 
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_event_types.eql"))
+        db.run_query(include_str!(
+            "./resources/query_null_comparison_predicate.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_coalesce() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_coalesce.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_subjects() {
+fn test_query_lazy_conditionals() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    load_payroll_dataset(&mut db);
 
+    // `e.data.name * 2` fails on every row but is never the picked branch
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_subjects.eql"))
+        db.run_query(include_str!("./resources/query_lazy_conditionals.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_agg_distinct() {
+fn test_query_lazy_if_null_condition() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    load_payroll_dataset(&mut db);
 
+    // a missing bonus makes the condition Null, which picks `otherwise` without touching the
+    // failing `then` branch
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_agg_distinct.eql"))
+        db.run_query(include_str!("./resources/query_lazy_if_null_condition.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_distinct() {
+fn test_query_three_valued_logic() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    load_payroll_dataset(&mut db);
 
+    // b has no bonus, so its predicate is unknown and the row gets dropped without failing the
+    // query, while d still matches through the other side of the OR
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_distinct.eql"))
+        db.run_query(include_str!("./resources/query_three_valued_logic.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_agg_functions() {
+fn test_query_in_list() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    insta::assert_yaml_snapshot!(
+        db.run_query(include_str!("./resources/query_in_list.eql"))
+            .unwrap()
+            .collect::<EvalResult<Vec<_>>>()
+    );
+}
+
+#[test]
+fn test_query_between() {
+    let mut db = InMemDb::default();
+
+    load_payroll_dataset(&mut db);
+
+    for (name, query) in [
+        (
+            "query_between",
+            include_str!("./resources/query_between.eql"),
+        ),
+        (
+            "query_between_incompatible_types",
+            include_str!("./resources/query_between_incompatible.eql"),
+        ),
+    ] {
+        insta::assert_yaml_snapshot!(
+            name,
+            db.run_query(query).unwrap().collect::<EvalResult<Vec<_>>>()
+        );
+    }
+}
+
+#[test]
+fn test_query_processor_map_and_filter_rows() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
 
-    let mut result = db
-        .run_query(include_str!("./resources/query_agg_functions.eql"))
+    let result = db
+        .run_query(include_str!("./resources/query_order_by.eql"))
         .unwrap()
-        .collect::<EvalResult<Vec<_>>>()
-        .unwrap();
+        .map_rows(|row| {
+            let QueryValue::Record(mut props) = row else {
+                return Err(EvalError::Runtime("expected a record".into()));
+            };
 
-    result.sort_by_key(|v| {
-        if let QueryValue::Record(props) = v {
-            props
-                .get("department")
-                .unwrap()
-                .as_str_or_panic()
-                .to_string()
-        } else {
-            "const".to_string()
-        }
-    });
+            let high_earner = matches!(props["salary"], QueryValue::Number(n) if n.0 > 90000.0);
+            props.insert("highEarner".to_string(), QueryValue::Bool(high_earner));
+
+            Ok(QueryValue::Record(props))
+        })
+        .filter_rows(|row| matches!(row, QueryValue::Record(props) if props["highEarner"] == QueryValue::Bool(true)))
+        .collect::<EvalResult<Vec<_>>>();
 
     insta::assert_yaml_snapshot!(result);
 }
 
 #[test]
-fn test_query_top() {
+fn test_query_stddev_variance_close_values() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    // the naive sum of squares formula gives a negative variance for these
+    for _ in 0..3 {
+        db.append(
+            "sensors/probe",
+            vec![Event {
+                event_type: "measured".to_string(),
+                datacontenttype: "application/json".to_string(),
+                data: serde_json::to_vec(&serde_json::json!({ "value": 123456789.4 })).unwrap(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
 
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_top.eql"))
-            .unwrap()
-            .collect::<EvalResult<Vec<_>>>()
+        db.run_query(include_str!(
+            "./resources/query_stddev_variance_close_values.eql"
+        ))
+        .unwrap()
+        .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_skip() {
+fn test_query_strict_aggregates_missing_field() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
+    db.query_options_mut().strict_aggregates = true;
 
     insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_skip.eql"))
+        db.run_query(include_str!("./resources/query_strict_aggregates.eql"))
             .unwrap()
             .collect::<EvalResult<Vec<_>>>()
     );
 }
 
 #[test]
-fn test_query_agg_top() {
+fn test_iter_subject_events_depth() {
     let mut db = InMemDb::default();
 
-    load_departments_dataset(&mut db);
+    for subject in [
+        "companies",
+        "companies/acme",
+        "companies/acme/users",
+        "companies/acme/users/james",
+        "companies/krispy",
+    ] {
+        db.append(
+            subject,
+            vec![Event {
+                event_type: "created".to_string(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
 
-    insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_agg_top.eql"))
-            .unwrap()
-            .collect::<EvalResult<Vec<_>>>()
+    assert_eq!(db.iter_subject_events_depth("companies", 0).count(), 1);
+    assert_eq!(db.iter_subject_events_depth("companies", 1).count(), 3);
+    assert_eq!(db.iter_subject_events_depth("companies", 2).count(), 4);
+    assert_eq!(
+        db.iter_subject_events_depth("companies", 10).count(),
+        db.iter_subject_events("companies").count()
     );
+    assert_eq!(db.iter_subject_events_depth("companies/*", 1).count(), 3);
 }
 
 #[test]
-fn test_query_agg_skip() {
+fn test_subject_paths_skip_empty_segments() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
 
-    insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/query_agg_skip.eql"))
+    db.append(
+        "krispy//boston/employees/nina_park/",
+        vec![Event {
+            event_type: "user-created".to_string(),
+            ..Default::default()
+        }],
+    )
+    .unwrap();
+
+    let ids = |path| {
+        db.iter_subject_events(path)
+            .map(|event| event.id)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(ids("krispy/boston"), ids("krispy/boston/"));
+    assert_eq!(ids("krispy/boston"), ids("krispy//boston"));
+    assert_eq!(ids("krispy/*/"), ids("krispy/*"));
+    assert_eq!(ids("krispy/boston").len(), 3);
+    assert_eq!(ids("krispy//nyc").len(), 2);
+    assert_eq!(ids("").len(), 7);
+
+    assert!(
+        db.iter_subjects()
+            .any(|subject| subject == "krispy/boston/employees/nina_park")
+    );
+    assert!(db.iter_subjects().all(|subject| !subject.is_empty()));
+    assert_eq!(
+        db.try_iter_subject_events("krispy//boston/")
             .unwrap()
-            .collect::<EvalResult<Vec<_>>>()
+            .count(),
+        3
     );
 }
 
 #[test]
-fn test_simple_sub_query() {
+fn test_iter_subject_events_wildcard() {
+    let mut db = InMemDb::default();
+
+    for subject in [
+        "companies/krispy/users/sarah",
+        "companies/acme/users/james",
+        "companies/acme/users",
+        "companies/acme/billing/invoices",
+    ] {
+        db.append(
+            subject,
+            vec![Event {
+                event_type: "created".to_string(),
+                ..Default::default()
+            }],
+        )
+        .unwrap();
+    }
+
+    assert_eq!(db.iter_subject_events("companies/*/users").count(), 3);
+    assert_eq!(db.iter_subject_events("companies/*/users/sarah").count(), 1);
+    assert_eq!(db.iter_subject_events("companies/*/*/invoices").count(), 1);
+    assert_eq!(db.iter_subject_events("companies/*/orders").count(), 0);
+    assert_eq!(db.iter_subject_events("companies/*").count(), 4);
+}
+
+#[test]
+fn test_try_iter_subject_events_missing_segment() {
     let mut db = InMemDb::default();
 
     load_departments_dataset(&mut db);
 
-    insta::assert_yaml_snapshot!(
-        db.run_query(include_str!("./resources/simple-sub-query.eql"))
+    assert_eq!(
+        db.try_iter_subject_events("krispy/nyc/employees")
             .unwrap()
-            .collect::<EvalResult<Vec<_>>>()
+            .count(),
+        db.iter_subject_events("krispy/nyc/employees").count()
     );
+
+    match db.try_iter_subject_events("krispy/nyc/employes/sarah_coleman") {
+        Err(Error::MissingSubjectSegment { ancestor, missing }) => {
+            assert_eq!(ancestor, "krispy/nyc");
+            assert_eq!(missing, "employes");
+        }
+
+        _ => panic!("expected a missing subject segment error"),
+    }
+
+    match db.try_iter_subject_events("acme") {
+        Err(Error::MissingSubjectSegment { ancestor, missing }) => {
+            assert_eq!(ancestor, "");
+            assert_eq!(missing, "acme");
+        }
+
+        _ => panic!("expected a missing subject segment error"),
+    }
+}
+
+#[test]
+fn test_strict_partial_cmp_against_total_order() {
+    let session = eventql_parser::Session::builder().use_stdlib().build();
+    let interpreter = Interpreter::new(Arc::new(session));
+    let number = QueryValue::Number(1.0.into());
+    let string = QueryValue::String("a".to_string());
+
+    // sorting keeps a total order across kinds of values
+    let mut values = vec![number.clone(), string.clone()];
+    values.sort();
+    assert!(values == vec![string.clone(), number.clone()]);
+
+    // while comparing them is unknown
+    assert_eq!(number.strict_partial_cmp(&string), None);
+    assert!(matches!(
+        interpreter.eval_binary(Operator::Lt, &number, &string),
+        Ok(QueryValue::Null)
+    ));
+
+    let low = QueryValue::Array(vec![number.clone(), QueryValue::Number(2.0.into())]);
+    let high = QueryValue::Array(vec![number.clone(), QueryValue::Number(3.0.into())]);
+    let mixed = QueryValue::Array(vec![number, string]);
+
+    assert_eq!(low.strict_partial_cmp(&high), Some(Ordering::Less));
+    assert!(matches!(
+        interpreter.eval_binary(Operator::Gte, &high, &low),
+        Ok(QueryValue::Bool(true))
+    ));
+    assert!(matches!(
+        interpreter.eval_binary(Operator::Lt, &low, &mixed),
+        Ok(QueryValue::Null)
+    ));
 }
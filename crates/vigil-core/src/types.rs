@@ -1,10 +1,15 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chrono::{DateTime, Utc};
 use eventql_parser::{Session, prelude::Type};
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::{eval::EvalResult, values::QueryValue};
+use crate::{
+    eval::EvalResult,
+    values::{QueryValue, Record},
+};
 
 #[derive(Default, Clone, Serialize)]
 pub struct Event {
@@ -15,10 +20,39 @@ pub struct Event {
     pub event_type: String,
     pub datacontenttype: String,
     pub data: Vec<u8>,
+    /// When the event got appended to the database. Left at its default, it's set at append time.
+    pub ingested_at: DateTime<Utc>,
 }
 
 impl Event {
-    pub fn project(&self, session: &Session, expected: Type) -> EvalResult<QueryValue> {
+    pub fn builder() -> EventBuilder {
+        EventBuilder::default()
+    }
+
+    /// Checks the attributes CloudEvents requires: a `1.0` spec version, a non-empty type and a
+    /// source that is a URI-reference. Returns why the event is invalid otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.spec_version != "1.0" {
+            return Err(format!("unsupported spec version '{}'", self.spec_version));
+        }
+
+        if self.event_type.is_empty() {
+            return Err("event type cannot be empty".to_string());
+        }
+
+        if !is_uri_reference(&self.source) {
+            return Err(format!("source '{}' is not a URI-reference", self.source));
+        }
+
+        Ok(())
+    }
+
+    pub fn project(
+        &self,
+        session: &Session,
+        decoders: &Decoders,
+        expected: Type,
+    ) -> EvalResult<QueryValue> {
         if let Type::Record(rec) = expected {
             let mut props = BTreeMap::new();
             for (name, value) in session.arena().get_type_rec(rec) {
@@ -90,35 +124,42 @@ impl Event {
                         }
                     },
 
-                    "data" => match value {
-                        Type::String => {
+                    // raw payload bytes, readable whatever the content type
+                    "data_base64" => match value {
+                        Type::String | Type::Unspecified => {
                             props.insert(
                                 name,
-                                QueryValue::String(unsafe {
-                                    str::from_utf8_unchecked(self.data.as_slice()).into()
-                                }),
+                                QueryValue::String(BASE64_STANDARD.encode(&self.data)),
                             );
                         }
 
+                        _ => {
+                            props.insert(name, QueryValue::Null);
+                        }
+                    },
+
+                    "ingested_at" => match value {
+                        Type::DateTime | Type::Unspecified => {
+                            props.insert(name, QueryValue::DateTime(self.ingested_at));
+                        }
+
+                        _ => {
+                            props.insert(name, QueryValue::Null);
+                        }
+                    },
+
+                    "data" => match value {
+                        Type::String => {
+                            // a payload that isn't valid UTF-8 can't be read as a string
+                            let data = str::from_utf8(self.data.as_slice())
+                                .map(|data| QueryValue::String(data.to_owned()))
+                                .unwrap_or(QueryValue::Null);
+
+                            props.insert(name, data);
+                        }
+
                         Type::Record(_) | Type::Unspecified => {
-                            match self.datacontenttype.as_str() {
-                                "application/json" => {
-                                    if let Ok(payload) = serde_json::from_slice(&self.data) {
-                                        props.insert(
-                                            name,
-                                            QueryValue::build_from_type_expectation(
-                                                session, payload, *value,
-                                            )?,
-                                        );
-                                    } else {
-                                        props.insert(name, QueryValue::Null);
-                                    }
-                                }
-
-                                _ => {
-                                    props.insert(name, QueryValue::Null);
-                                }
-                            }
+                            props.insert(name, self.decode_data(session, decoders, *value)?);
                         }
 
                         _ => {
@@ -132,9 +173,229 @@ impl Event {
                 }
             }
 
+            // fields come in the type's interning order, keep them sorted by name
+            Ok(QueryValue::Record(props.into_iter().collect()))
+        } else if let Type::Unspecified = expected {
+            // no declared shape, project the whole envelope, sorted by name like above
+            let mut props = Record::new();
+            props.insert(
+                "data".to_owned(),
+                self.decode_data(session, decoders, expected)?,
+            );
+            props.insert(
+                "datacontenttype".to_owned(),
+                QueryValue::String(self.datacontenttype.clone()),
+            );
+            props.insert("id".to_owned(), QueryValue::String(self.id.to_string()));
+            props.insert(
+                "ingested_at".to_owned(),
+                QueryValue::DateTime(self.ingested_at),
+            );
+            props.insert("source".to_owned(), QueryValue::String(self.source.clone()));
+            props.insert(
+                "spec_version".to_owned(),
+                QueryValue::String(self.spec_version.clone()),
+            );
+            props.insert(
+                "subject".to_owned(),
+                QueryValue::String(self.subject.clone()),
+            );
+            props.insert(
+                "type".to_owned(),
+                QueryValue::String(self.event_type.clone()),
+            );
+
             Ok(QueryValue::Record(props))
         } else {
             Ok(QueryValue::Null)
         }
     }
+
+    /// Decodes the payload with the decoder registered for its content type, falling back to the
+    /// built-in CSV support. Unsupported content types are `Null`.
+    fn decode_data(
+        &self,
+        session: &Session,
+        decoders: &Decoders,
+        expected: Type,
+    ) -> EvalResult<QueryValue> {
+        if let Some(decoder) = decoders.get(&self.datacontenttype) {
+            return match decoder(&self.data) {
+                Some(payload) => {
+                    QueryValue::build_from_type_expectation(session, payload, expected)
+                }
+                None => Ok(QueryValue::Null),
+            };
+        }
+
+        match self.datacontenttype.as_str() {
+            "text/csv" => Ok(decode_csv(&self.data)),
+            _ => Ok(QueryValue::Null),
+        }
+    }
+}
+
+/// Reads an event payload as JSON, `None` when the payload is malformed.
+pub type DataDecoder = Box<dyn Fn(&[u8]) -> Option<serde_json::Value> + Send + Sync>;
+
+/// Payload decoders keyed by content type. `application/json` is registered out of the box.
+pub struct Decoders {
+    inner: HashMap<String, DataDecoder>,
+}
+
+impl Decoders {
+    /// Registers `decoder` for `content_type`, replacing any previous one.
+    pub fn register<F>(&mut self, content_type: impl Into<String>, decoder: F)
+    where
+        F: Fn(&[u8]) -> Option<serde_json::Value> + Send + Sync + 'static,
+    {
+        self.inner.insert(content_type.into(), Box::new(decoder));
+    }
+
+    pub fn get(&self, content_type: &str) -> Option<&DataDecoder> {
+        self.inner.get(content_type)
+    }
+}
+
+impl Default for Decoders {
+    fn default() -> Self {
+        let mut decoders = Self {
+            inner: HashMap::new(),
+        };
+
+        decoders.register("application/json", |data| serde_json::from_slice(data).ok());
+        decoders
+    }
+}
+
+/// Fluent construction of an [`Event`]. [`EventBuilder::build`] fills in a fresh id and a default
+/// spec version when none were given.
+#[derive(Default)]
+pub struct EventBuilder {
+    inner: Event,
+}
+
+impl EventBuilder {
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.inner.id = id;
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.inner.source = source.into();
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.inner.subject = subject.into();
+        self
+    }
+
+    pub fn event_type(mut self, event_type: impl Into<String>) -> Self {
+        self.inner.event_type = event_type.into();
+        self
+    }
+
+    pub fn ingested_at(mut self, ingested_at: DateTime<Utc>) -> Self {
+        self.inner.ingested_at = ingested_at;
+        self
+    }
+
+    pub fn data(mut self, content_type: impl Into<String>, data: Vec<u8>) -> Self {
+        self.inner.datacontenttype = content_type.into();
+        self.inner.data = data;
+        self
+    }
+
+    /// Serializes `payload` as the event data, with an `application/json` content type.
+    pub fn json_data<T: Serialize>(self, payload: &T) -> serde_json::Result<Self> {
+        let data = serde_json::to_vec(payload)?;
+        Ok(self.data("application/json", data))
+    }
+
+    pub fn build(mut self) -> Event {
+        if self.inner.id.is_nil() {
+            self.inner.id = Uuid::new_v4();
+        }
+
+        if self.inner.spec_version.is_empty() {
+            self.inner.spec_version = "1.0".to_string();
+        }
+
+        self.inner
+    }
+}
+
+/// Tells whether `s` is a non-empty URI-reference (RFC 3986): only URI characters, well-formed
+/// percent-encodings and, when it has one, a valid scheme.
+fn is_uri_reference(s: &str) -> bool {
+    if s.is_empty() {
+        return false;
+    }
+
+    let bytes = s.as_bytes();
+    for (idx, b) in bytes.iter().enumerate() {
+        let valid = match b {
+            b'%' => {
+                bytes.get(idx + 1).is_some_and(u8::is_ascii_hexdigit)
+                    && bytes.get(idx + 2).is_some_and(u8::is_ascii_hexdigit)
+            }
+
+            b if b.is_ascii_alphanumeric() => true,
+            _ => b"-._~:/?#[]@!$&'()*+,;=".contains(b),
+        };
+
+        if !valid {
+            return false;
+        }
+    }
+
+    // a colon before any path, query or fragment delimiter ends a scheme
+    let head = s.split(['/', '?', '#']).next().unwrap_or_default();
+    if let Some((scheme, _)) = head.split_once(':') {
+        let mut chars = scheme.chars();
+
+        return chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    }
+
+    true
+}
+
+/// Decodes a CSV payload whose first line holds the column names. A single row becomes a record,
+/// several rows an array of records and malformed CSV is `Null`.
+fn decode_csv(data: &[u8]) -> QueryValue {
+    let mut reader = csv::Reader::from_reader(data);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(_) => return QueryValue::Null,
+    };
+
+    let mut rows = Vec::new();
+    for row in reader.records() {
+        let Ok(row) = row else {
+            return QueryValue::Null;
+        };
+
+        let record = headers
+            .iter()
+            .zip(row.iter())
+            .map(|(column, cell)| {
+                let value = cell
+                    .parse::<f64>()
+                    .map(|n| QueryValue::Number(n.into()))
+                    .unwrap_or_else(|_| QueryValue::String(cell.to_owned()));
+
+                (column.to_owned(), value)
+            })
+            .collect::<Record>();
+
+        rows.push(QueryValue::Record(record));
+    }
+
+    if rows.len() == 1 {
+        rows.pop().unwrap_or(QueryValue::Null)
+    } else {
+        QueryValue::Array(rows)
+    }
 }
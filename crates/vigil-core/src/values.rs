@@ -1,8 +1,14 @@
 use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use eventql_parser::{Session, Type};
+use indexmap::IndexMap;
 use ordered_float::OrderedFloat;
-use serde::Serialize;
-use std::collections::BTreeMap;
+use serde::{Serialize, Serializer, de::DeserializeOwned};
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    mem,
+    ops::Index,
+};
 
 use crate::eval::{EvalError, EvalResult};
 
@@ -10,9 +16,10 @@ use crate::eval::{EvalError, EvalResult};
 pub enum QueryValue {
     Null,
     String(String),
+    #[serde(serialize_with = "serialize_number")]
     Number(OrderedFloat<f64>),
     Bool(bool),
-    Record(BTreeMap<String, QueryValue>),
+    Record(Record),
     Array(Vec<QueryValue>),
     DateTime(DateTime<Utc>),
     Date(NaiveDate),
@@ -29,6 +36,46 @@ impl QueryValue {
         panic!("expected a string but got something else")
     }
 
+    /// Orders two values only when they are of the same kind. Unlike the total [`Ord`] used for
+    /// sorting, a `Number` and a `String` are incomparable instead of ordered by variant.
+    pub fn strict_partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::String(a), Self::String(b)) => Some(a.cmp(b)),
+            (Self::Number(a), Self::Number(b)) => Some(a.cmp(b)),
+            (Self::Bool(a), Self::Bool(b)) => Some(a.cmp(b)),
+            (Self::DateTime(a), Self::DateTime(b)) => Some(a.cmp(b)),
+            (Self::Date(a), Self::Date(b)) => Some(a.cmp(b)),
+            (Self::Time(a), Self::Time(b)) => Some(a.cmp(b)),
+            (Self::Array(a), Self::Array(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.strict_partial_cmp(b)? {
+                        Ordering::Equal => continue,
+                        ordering => return Some(ordering),
+                    }
+                }
+
+                Some(a.len().cmp(&b.len()))
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Plain JSON view of the value, see [`PlainJson`].
+    pub fn plain_json(&self) -> PlainJson<'_> {
+        PlainJson(self)
+    }
+
+    /// Deserializes the value into `T` through its plain JSON view (see [`PlainJson`]), so a record
+    /// row maps onto a struct with the same field names.
+    pub fn deserialize_into<T: DeserializeOwned>(&self) -> EvalResult<T> {
+        serde_json::to_value(self.plain_json())
+            .and_then(serde_json::from_value)
+            .map_err(|e| {
+                EvalError::Runtime(format!("failed to deserialize query value: {e}").into())
+            })
+    }
+
     pub fn from(value: serde_json::Value) -> QueryValue {
         match value {
             serde_json::Value::Null => QueryValue::Null,
@@ -46,7 +93,7 @@ impl QueryValue {
                 QueryValue::Array(values)
             }
             serde_json::Value::Object(map) => {
-                let mut props = BTreeMap::new();
+                let mut props = Record::new();
                 for (name, value) in map {
                     props.insert(name, Self::from(value));
                 }
@@ -107,7 +154,7 @@ impl QueryValue {
             Type::Record(map) => {
                 if let serde_json::Value::Object(values) = value {
                     let map = session.arena().get_type_rec(map);
-                    let mut props = BTreeMap::new();
+                    let mut props = Record::new();
 
                     for (prop_name, prop_value) in values {
                         let prop_value =
@@ -169,3 +216,163 @@ impl QueryValue {
         }
     }
 }
+
+/// Emits whole numbers as integers, so a count renders as `3` rather than `3.0`. `-0.0` stays a
+/// float to keep its sign, and so do numbers out of the `i64` range.
+fn serialize_number<S: Serializer>(
+    n: &OrderedFloat<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    // `i64::MAX as f64` rounds up to 2^63, which doesn't fit in an `i64`
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 && !(n.0 == 0.0 && n.is_sign_negative()) {
+        serializer.serialize_i64(n.0 as i64)
+    } else {
+        serializer.serialize_f64(n.0)
+    }
+}
+
+/// Serializes a value as plain JSON, without the variant tags [`QueryValue`]'s own `Serialize`
+/// impl produces. Records keep their field order, temporal values become strings and numbers that
+/// aren't finite become `null`.
+pub struct PlainJson<'a>(&'a QueryValue);
+
+impl Serialize for PlainJson<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            QueryValue::Null => serializer.serialize_unit(),
+            QueryValue::String(s) => serializer.serialize_str(s),
+            QueryValue::Number(n) if !n.is_finite() => serializer.serialize_unit(),
+            QueryValue::Number(n) => serialize_number(n, serializer),
+            QueryValue::Bool(b) => serializer.serialize_bool(*b),
+            QueryValue::Record(record) => {
+                serializer.collect_map(record.iter().map(|(name, value)| (name, PlainJson(value))))
+            }
+            QueryValue::Array(values) => serializer.collect_seq(values.iter().map(PlainJson)),
+            QueryValue::DateTime(t) => serializer.serialize_str(&t.to_rfc3339()),
+            QueryValue::Date(d) => serializer.collect_str(d),
+            QueryValue::Time(t) => serializer.collect_str(t),
+        }
+    }
+}
+
+/// Record fields, kept in insertion order so a projection renders its fields the way they were
+/// declared. Equality, ordering and hashing ignore that order.
+#[derive(Clone, Default)]
+pub struct Record {
+    fields: IndexMap<String, QueryValue>,
+    // positions of `fields` ordered by name, kept up to date so comparing and hashing records,
+    // done for every row of a GROUP BY or ORDER BY, doesn't need to sort them
+    by_name: Vec<usize>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: String, value: QueryValue) -> Option<QueryValue> {
+        if let Some(slot) = self.fields.get_mut(&name) {
+            return Some(mem::replace(slot, value));
+        }
+
+        let at = self.by_name.partition_point(|index| {
+            self.fields
+                .get_index(*index)
+                .is_some_and(|(field, _)| *field < name)
+        });
+
+        self.by_name.insert(at, self.fields.len());
+        self.fields.insert(name, value);
+
+        None
+    }
+
+    pub fn get(&self, name: &str) -> Option<&QueryValue> {
+        self.fields.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &QueryValue)> {
+        self.fields.iter()
+    }
+
+    /// Fields ordered by name.
+    fn sorted(&self) -> impl Iterator<Item = (&String, &QueryValue)> {
+        self.by_name
+            .iter()
+            .filter_map(|index| self.fields.get_index(*index))
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        // IndexMap equality doesn't depend on insertion order
+        self.fields == other.fields
+    }
+}
+
+impl Eq for Record {}
+
+impl PartialOrd for Record {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Record {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sorted().cmp(other.sorted())
+    }
+}
+
+impl Hash for Record {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+
+        for entry in self.sorted() {
+            entry.hash(state);
+        }
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.fields.iter())
+    }
+}
+
+impl Index<&str> for Record {
+    type Output = QueryValue;
+
+    fn index(&self, name: &str) -> &Self::Output {
+        &self.fields[name]
+    }
+}
+
+impl FromIterator<(String, QueryValue)> for Record {
+    fn from_iter<T: IntoIterator<Item = (String, QueryValue)>>(iter: T) -> Self {
+        let mut record = Self::new();
+
+        for (name, value) in iter {
+            record.insert(name, value);
+        }
+
+        record
+    }
+}
+
+impl IntoIterator for Record {
+    type Item = (String, QueryValue);
+    type IntoIter = indexmap::map::IntoIter<String, QueryValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter()
+    }
+}